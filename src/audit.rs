@@ -0,0 +1,89 @@
+use std::io::Write;
+
+use chrono::prelude::*;
+
+use crate::config::Config;
+use crate::error::Error;
+
+/// Record an operation in the audit log, if one is configured, and
+/// pass `result` through. Each operation is one JSON line with these
+/// fields:
+///
+/// - `timestamp`: when the operation finished, in RFC 3339.
+/// - `operation`: name of the operation, like `reveal` or `rotate`.
+/// - `path`: the Vault path operated on, or null if there is none.
+/// - `success`: whether the operation succeeded.
+///
+/// Nothing else is written. In particular no field names, values,
+/// tokens, or error messages, so that the log never has secrets.
+pub fn record<T>(conf: &Config, operation: &str, path: Option<&str>,
+                 result: Result<T, Error>) -> Result<T, Error>
+{
+    if let Some(file) = &conf.audit_log
+    {
+        let line = serde_json::json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "operation": operation,
+            "path": path,
+            "success": result.is_ok(),
+        });
+        // An access that cannot be recorded is an error, even if the
+        // operation itself succeeded.
+        append(file, &format!("{}\n", line))?;
+    }
+    result
+}
+
+/// Append `line` to `file`, creating it readable only by the user on
+/// Unix. The line is written with a single write to a file opened
+/// for appending, while holding an exclusive lock on the file, so
+/// lines from concurrent runs do not interleave.
+fn append(file: &str, line: &str) -> Result<(), Error>
+{
+    let mut options = std::fs::OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut f = options.open(file).map_err(
+        |e| rterr!("Failed to open audit log {}: {}", file, e))?;
+    // The lock is released when the file is closed.
+    f.lock().map_err(
+        |e| rterr!("Failed to lock audit log {}: {}", file, e))?;
+    f.write_all(line.as_bytes()).map_err(
+        |e| rterr!("Failed to write audit log {}: {}", file, e))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn concurrentRecordsDoNotInterleave()
+    {
+        let file = std::env::temp_dir().join(format!(
+            "vault-hunter-test-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        let mut conf = Config::default();
+        conf.audit_log = Some(file.to_string_lossy().into_owned());
+        let path = "x".repeat(4096);
+        std::thread::scope(|scope| for _ in 0..8
+        {
+            scope.spawn(|| for _ in 0..20
+            {
+                record(&conf, "reveal", Some(&path), Ok(())).unwrap();
+            });
+        });
+        let content = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(content.lines().count(), 160);
+        for line in content.lines()
+        {
+            let line: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(line["path"], path.as_str());
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::env;
 use std::path::{Path, PathBuf};
 
+use indexmap::IndexMap;
 use serde::Deserialize;
 
 use crate::error::Error;
@@ -31,26 +32,28 @@ fn findConfigDir() -> Option<PathBuf>
     None
 }
 
-pub fn findConfigFile() -> Option<PathBuf>
+/// Return where the config file should be, whether or not it
+/// exists. Return None if the path cannot be determined.
+pub fn configFilePath() -> Option<PathBuf>
 {
     let basename = "config.toml";
-    if let Some(mut p) = findConfigDir()
-    {
+    findConfigDir().map(|mut p| {
         p.push(basename);
-        if p.exists()
-        {
-            return Some(p);
-        }
-    }
-    None
+        p
+    })
+}
+
+pub fn findConfigFile() -> Option<PathBuf>
+{
+    configFilePath().filter(|p| p.exists())
 }
 
 /// Return the default path of the runtime info file. Return what the
 /// path should be if the files does not exist. Return None if the
 /// path cannot be determined.
-fn findRuntimeInfoFile() -> Option<PathBuf>
+fn findRuntimeInfoFile(format: &str) -> Option<PathBuf>
 {
-    let basename = "runtime.json";
+    let basename = format!("runtime.{}", format);
     if let Some(mut p) = findConfigDir()
     {
         p.push(basename);
@@ -60,27 +63,304 @@ fn findRuntimeInfoFile() -> Option<PathBuf>
 }
 
 fn defaultXMLExportPeriod() -> i64 { 86400 }
+fn defaultConcurrency() -> usize { 8 }
+fn defaultMaxResponseBytes() -> usize { 16 * 1024 * 1024 }
+fn defaultTrue() -> bool { true }
+fn defaultPasswordLength() -> usize { 24 }
+fn defaultRotationWarnDays() -> i64 { 14 }
+fn defaultAuthMethod() -> String { String::from("userpass") }
+fn defaultJSONField() -> String { String::from("Password") }
+fn defaultUsernameField() -> String { String::from("Username") }
+fn defaultTOTPField() -> String { String::from("TOTP") }
+fn defaultClipboardClear() -> u64 { 20 }
+fn defaultRuntimeFormat() -> String { String::from("json") }
+fn defaultClipboardSelection() -> String { String::from("clipboard") }
+fn defaultOIDCCallbackAddress() -> String { String::from("127.0.0.1") }
+fn defaultOIDCCallbackPort() -> u16 { 8250 }
+fn defaultOIDCTimeout() -> u64 { 300 }
+fn defaultDataPrefix() -> String { String::from("data") }
+fn defaultMetadataPrefix() -> String { String::from("metadata") }
+fn defaultKVVersion() -> u8 { 2 }
+
+/// Arguments that make the clipboard program `name` copy to
+/// `selection`. Return None if the program does not support it.
+fn selectionArgs(name: &str, selection: &str) -> Option<Vec<&'static str>>
+{
+    match (name, selection)
+    {
+        ("xclip", "clipboard") => Some(vec!["-selection", "clipboard"]),
+        ("xclip", "primary") => Some(vec!["-selection", "primary"]),
+        ("xsel", "clipboard") => Some(vec!["--clipboard", "--input"]),
+        ("xsel", "primary") => Some(vec!["--primary", "--input"]),
+        ("wl-copy", "clipboard") => Some(Vec::new()),
+        ("wl-copy", "primary") => Some(vec!["--primary"]),
+        (_, "clipboard") => Some(Vec::new()),
+        _ => None,
+    }
+}
+
+/// The program and arguments that print `selection`, for the
+/// clipboard program `prog`. Return None if it is not known.
+fn pasteCommand(prog: &str, selection: &str) ->
+    Option<(String, Vec<&'static str>)>
+{
+    let name = Path::new(prog).file_stem().and_then(|n| n.to_str())?;
+    match (name, selection)
+    {
+        ("xclip", "clipboard") => Some((prog.to_owned(),
+                                        vec!["-o", "-selection", "clipboard"])),
+        ("xclip", "primary") => Some((prog.to_owned(),
+                                      vec!["-o", "-selection", "primary"])),
+        ("xsel", "clipboard") => Some((prog.to_owned(),
+                                       vec!["--clipboard", "--output"])),
+        ("xsel", "primary") => Some((prog.to_owned(),
+                                     vec!["--primary", "--output"])),
+        ("wl-copy", "clipboard") => Some((String::from("wl-paste"),
+                                          vec!["--no-newline"])),
+        ("wl-copy", "primary") => Some((String::from("wl-paste"),
+                                        vec!["--no-newline", "--primary"])),
+        ("pbcopy", "clipboard") => Some((String::from("pbpaste"), Vec::new())),
+        _ => None,
+    }
+}
+
+/// Normalize the URL of the Vault API so that it ends with a slash.
+pub fn normalizeEndPoint(url: &str) -> String
+{
+    let url = url.trim();
+    if url.ends_with('/')
+    {
+        url.to_owned()
+    }
+    else
+    {
+        format!("{}/", url)
+    }
+}
+
+/// The address of Vault in `VAULT_ADDR`, like the Vault CLI uses.
+pub fn vaultAddr() -> Option<String>
+{
+    std::env::var("VAULT_ADDR").ok().filter(|a| !a.trim().is_empty())
+        .map(|a| normalizeEndPoint(&a))
+}
+
+/// Whether `s` looks like a URL for `parseVaultURL`.
+pub fn isVaultURL(s: &str) -> bool
+{
+    s.starts_with("vault://") || s.starts_with("vault+http://")
+}
+
+/// Parse a URL like `vault://user@host[:port]/mount/path` into a
+/// config and the path of an entry, for use without a config file.
+/// The API is reached at `https://host[:port]/`, or with plain HTTP
+/// if the scheme is `vault+http`. The user is the username in entry
+/// paths and for login. The first component of the path is the KV
+/// mount, and the rest is the path of the entry in it. A path that is
+/// empty or ends with a slash is a directory.
+pub fn parseVaultURL(s: &str) -> Result<(Config, String), Error>
+{
+    let url = reqwest::Url::parse(s).map_err(
+        |e| error!(ConfigError, "Invalid URL {}: {}", s, e))?;
+    let http_scheme = match url.scheme()
+    {
+        "vault" => "https",
+        "vault+http" => "http",
+        scheme => return Err(error!(ConfigError, "Invalid URL scheme {}. \
+                                                  Use vault or vault+http",
+                                    scheme)),
+    };
+    let host = url.host_str().filter(|h| !h.is_empty()).ok_or_else(
+        || error!(ConfigError, "No host in {}", s))?;
+    if url.username().is_empty()
+    {
+        return Err(error!(ConfigError, "No user in {}", s));
+    }
+    let host = if let Some(port) = url.port()
+    {
+        format!("{}:{}", host, port)
+    }
+    else
+    {
+        host.to_owned()
+    };
+    let path = url.path().trim_start_matches('/');
+    let (mount, path) = path.split_once('/').unwrap_or((path, ""));
+    if mount.is_empty()
+    {
+        return Err(error!(ConfigError, "No mount in {}", s));
+    }
+    let mut conf = Config {
+        end_point: format!("{}://{}/", http_scheme, host),
+        username: url.username().to_owned(),
+        mount: Some(mount.to_owned()),
+        ..Config::default()
+    };
+    // Keep the token of this Vault apart from the configured one.
+    conf.cache_path = findConfigDir().map(|mut p| {
+        p.push(format!("runtime-{}.{}", host.replace(':', "_"),
+                       conf.runtime_format));
+        p.to_string_lossy().into_owned()
+    });
+    Ok((conf, path.to_owned()))
+}
+
+/// Describe a parse error of the config file `content`, naming the
+/// field if the error is in the value of a field.
+fn describeParseError(content: &str, e: &toml::de::Error) -> String
+{
+    let span = if let Some(span) = e.span()
+    {
+        span
+    }
+    else
+    {
+        return e.message().to_owned();
+    };
+    let line_start = content[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_no = content[..span.start].matches('\n').count() + 1;
+    let line = content[line_start..].lines().next().unwrap_or("");
+    if let Some((key, _)) = line.split_once('=')
+    {
+        // Only blame the field if the error is after the `=`.
+        if span.start - line_start > key.len() && !key.trim().is_empty()
+        {
+            return format!("invalid value for `{}` at line {}: {}",
+                           key.trim(), line_no, e.message());
+        }
+    }
+    if span.start == 0
+    {
+        // Errors about the whole document, like missing fields.
+        e.message().to_owned()
+    }
+    else
+    {
+        format!("line {}: {}", line_no, e.message())
+    }
+}
 
 #[derive(Deserialize)]
 pub struct Config
 {
-    /// CA certificates files for HTTPS
+    /// CA certificates files for HTTPS. An item can also be a
+    /// directory, in which case all the `.pem` and `.crt` files in it
+    /// are used.
     #[serde(default)]
     pub ca_certs: Vec<String>,
-    /// End point to the Vault HTTP API
+    /// Whether to trust the root certificates of the system, in
+    /// addition to `ca_certs`. If false, only `ca_certs` are trusted.
+    #[serde(default = "defaultTrue")]
+    pub trust_system_roots: bool,
+    /// End point to the Vault HTTP API. If not set, `VAULT_ADDR` is
+    /// used. Overridden by `--endpoint`.
+    #[serde(default)]
     pub end_point: String,
+    /// Path of the KV mount with the entries. Defaults to
+    /// `passwords`.
+    pub mount: Option<String>,
     /// The username. The userpass authentication in Vault
     /// automatically lowercase this. So it does not have to be
-    /// all-lowercase in the config file.
+    /// all-lowercase in the config file. It can be left out if
+    /// `username_from_token` is set and the token has a username.
+    #[serde(default)]
     username: String,
+    /// Take the username in entry paths from the metadata of the
+    /// token after login, instead of `username`. `username` is still
+    /// used if the token does not have one, and to login with a
+    /// password.
+    #[serde(default)]
+    pub username_from_token: bool,
     /// A program that copy the content of stdin to the OS’s
-    /// clipboard. By default this `xclip` in Linux, and `pbcopy` in
-    /// macOS. Password is piped to this program. If this is not
-    /// found, the password is printed.
+    /// clipboard. By default this `xclip` in Linux, `pbcopy` in
+    /// macOS, and `clip` in Windows. Password is piped to this
+    /// program. If this is not found, the password is printed.
     pub clipboard_prog: Option<String>,
+    /// Which X selection to copy to: `clipboard`, `primary` (pasted
+    /// with the middle button), or `both`. This is supported with
+    /// `xclip`, `xsel`, and `wl-copy`. Other programs only have the
+    /// clipboard.
+    #[serde(default = "defaultClipboardSelection")]
+    pub clipboard_selection: String,
+    /// Fail if the clipboard program cannot be run, instead of
+    /// printing the secret.
+    #[serde(default)]
+    pub clipboard_required: bool,
+    /// Read the password for `userpass` or `ldap` login from this file
+    /// instead of the terminal. This is meant for a FIFO written by a
+    /// graphical prompt, which should write the password as a single
+    /// line. Overridden by `--askpass`.
+    pub askpass: Option<String>,
+    /// How to login: `userpass` (the default), `ldap` with the LDAP
+    /// username and password, `jwt` / `oidc` to login with a JWT
+    /// obtained elsewhere, or `oidc_browser` to login with the OIDC
+    /// provider in a browser. The JWT is taken from
+    /// `--jwt`, the environment variable `VH_JWT`, or `jwt_file`, in
+    /// that order.
+    #[serde(default = "defaultAuthMethod")]
+    pub auth_method: String,
+    /// Role to login as with `jwt`, `oidc`, or `oidc_browser`. If not
+    /// set, the default role of the auth mount is used.
+    pub jwt_role: Option<String>,
+    /// File with the JWT to login with.
+    pub jwt_file: Option<String>,
+    /// Role ID to login with AppRole, for jobs without a user. If this
+    /// is set, AppRole is used instead of `auth_method`.
+    pub approle_role_id: Option<String>,
+    /// Secret ID of the AppRole. Anyone who can read the config file
+    /// can login with it, so `approle_secret_id_file` is better.
+    pub approle_secret_id: Option<String>,
+    /// File with the secret ID of the AppRole. The secret ID is not
+    /// sent if neither this nor `approle_secret_id` is set, which only
+    /// works if the role does not require one.
+    pub approle_secret_id_file: Option<String>,
+    /// Address to listen on for the redirect of `oidc_browser` login.
+    #[serde(default = "defaultOIDCCallbackAddress")]
+    pub oidc_callback_address: String,
+    /// Port to listen on for the redirect of `oidc_browser` login.
+    /// The redirect URI `http://<host>:<port>/oidc/callback` has to be
+    /// allowed in the role. The host is `oidc_callback_address`, or
+    /// `localhost` if that is a loopback or unspecified address.
+    #[serde(default = "defaultOIDCCallbackPort")]
+    pub oidc_callback_port: u16,
+    /// How long to wait for the browser login to complete.
+    #[serde(default = "defaultOIDCTimeout")]
+    pub oidc_timeout_secs: u64,
+    /// JWT from the command line.
+    #[serde(skip)]
+    pub jwt: Option<String>,
+    /// Vault token from the command line.
+    #[serde(skip)]
+    pub token: Option<String>,
+    /// Type of token to request at login, `service` or `batch`. Batch
+    /// tokens are cheaper for Vault because they are not persisted,
+    /// but they cannot be renewed or revoked. If not set, the default
+    /// of the auth mount is used.
+    pub token_type: Option<String>,
+    /// A program to run whenever an entry is revealed. The path of
+    /// the entry is in the environment variable `VH_PATH`, and each
+    /// field is in `VH_FIELD_<name>`, with characters other than ASCII
+    /// letters and digits in the name replaced by `_`. For example
+    /// the password is in `VH_FIELD_Password`. Note that the program
+    /// gets all the secrets of the entry, and environment variables
+    /// are inherited by its children.
+    pub reveal_hook: Option<String>,
     /// Location of the cache file that stores the token. By default
     /// it’s $XDG_CONFIG_HOME/vault-hunter/runtime-info.json
     pub cache_path: Option<String>,
+    /// Use the cached token, or the one from `--token` or
+    /// `VAULT_TOKEN`, without asking Vault whether it is still
+    /// valid. This saves a round trip on each run, and works better
+    /// on a slow or flaky connection. The cost is that an expired or
+    /// revoked token is only noticed when a request is refused. The
+    /// token is then checked, and if it is invalid, the request is
+    /// sent again after a fresh login. Overridden by `--prefer-cache`.
+    #[serde(default)]
+    pub prefer_cache: bool,
+    /// Format of the runtime info file, `json` or `toml`. When
+    /// switching to `toml`, an existing JSON file is migrated.
+    #[serde(default = "defaultRuntimeFormat")]
+    pub runtime_format: String,
     /// Whether and where to regularly export a local encrypted XML of
     /// all the passwords.
     pub local_xml: Option<String>,
@@ -89,6 +369,119 @@ pub struct Config
     /// Time period of XML export.
     #[serde(default = "defaultXMLExportPeriod")]
     pub xml_export_period: i64,
+    /// Write Prometheus metrics about each export to this file, for
+    /// the textfile collector of the node exporter. Overridden by
+    /// `--metrics-file`.
+    pub metrics_file: Option<String>,
+    /// Append the changes reported by `--diff-export` to this file.
+    pub export_diff_file: Option<String>,
+    /// Other config files searched along with this one by
+    /// `--merge-profiles`, by profile name. A profile without
+    /// `cache_path` caches its token in `runtime-<name>.<format>` in
+    /// the config directory.
+    #[serde(default)]
+    pub profiles: IndexMap<String, String>,
+    /// Append a JSON line for each operation to this file. See the
+    /// `audit` module for what is recorded.
+    pub audit_log: Option<String>,
+    /// Check that the local XML can be decrypted and read back after
+    /// each export.
+    #[serde(default)]
+    pub verify_export: bool,
+    /// Refuse all operations that write to Vault.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Field printed by `--pretty-json` if none is given.
+    #[serde(default = "defaultJSONField")]
+    pub json_field: String,
+    /// Name of the field with a TOTP secret, in base32 or as an
+    /// `otpauth://` URI. When an entry with this field is revealed,
+    /// the current code is printed.
+    #[serde(default = "defaultTOTPField")]
+    pub totp_field: String,
+    /// Name of the field copied by `--copy-username`.
+    #[serde(default = "defaultUsernameField")]
+    pub username_field: String,
+    /// Warn when revealing an entry whose `__expires` date is within
+    /// this many days.
+    #[serde(default = "defaultRotationWarnDays")]
+    pub rotation_warn_days: i64,
+    /// Number the choices in menus from 1 instead of 0.
+    #[serde(default)]
+    pub one_based_selection: bool,
+    /// Length of generated passwords.
+    #[serde(default = "defaultPasswordLength")]
+    pub password_length: usize,
+    /// Fields whose values are shown as `****` when revealing an
+    /// entry, unless `--show-all` is given. This only affects the
+    /// display; the values can still be copied.
+    #[serde(default)]
+    pub redact_fields: Vec<String>,
+    /// Fields whose values are encrypted with `client_key` before they
+    /// are written to Vault, and decrypted when an entry is read. The
+    /// values can then not be read by anyone who can read Vault, like
+    /// a Vault admin, a leaked token, or a backup of the storage,
+    /// without also having the key. This does not protect against
+    /// anyone who can read the key on this machine, and the names of
+    /// the fields, the other fields, and the paths are still in
+    /// plaintext. Everything vault-hunter outputs has the values
+    /// decrypted, including exports.
+    #[serde(default)]
+    pub client_encrypted_fields: Vec<String>,
+    /// An age identity file to encrypt and decrypt
+    /// `client_encrypted_fields` with. Requires the `age` program.
+    pub client_key: Option<String>,
+    /// Fields allowed besides the revealed one in strict mode.
+    #[serde(default)]
+    pub strict_allowed_fields: Vec<String>,
+    /// Do not print informational messages.
+    #[serde(default)]
+    pub quiet: bool,
+    /// Clear the clipboard this many seconds after copying a secret,
+    /// 20 by default. 0 means do not clear. If the clipboard can be
+    /// read and no longer has the secret, it is left alone. Also
+    /// accepted as `clipboard_clear_seconds`. Overridden by
+    /// `--clear-after`.
+    #[serde(default = "defaultClipboardClear",
+            alias = "clipboard_clear_seconds")]
+    pub clipboard_clear_secs: u64,
+    /// Print a trace of each HTTP request to stderr, with credentials
+    /// and bodies redacted.
+    #[serde(default)]
+    pub trace: bool,
+    /// Maximal size of a response body from Vault. A larger response
+    /// is an error.
+    #[serde(default = "defaultMaxResponseBytes")]
+    pub max_response_bytes: usize,
+    /// Maximal number of requests to Vault in flight at the same time.
+    #[serde(default = "defaultConcurrency")]
+    pub concurrency: usize,
+    /// Maximal number of idle connections kept per host. Unlimited
+    /// by default.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// If `end_point` is a standby node of an HA cluster, resend the
+    /// requests that it redirects (or answers with 429) to the active
+    /// node. The address of the active node comes from the redirect,
+    /// or from `sys/leader`. This costs an extra round trip for each
+    /// such request.
+    #[serde(default)]
+    pub follow_standby_redirect: bool,
+    /// Talk HTTP/2 to Vault without negotiating it first. Only use
+    /// this if Vault is known to support HTTP/2.
+    #[serde(default)]
+    pub http2_prior_knowledge: bool,
+    /// The path segment after the mount for reading entries. This is
+    /// `data` in KV v2, but some proxies rewrite it.
+    #[serde(default = "defaultDataPrefix")]
+    pub data_prefix: String,
+    /// The path segment after the mount for listing and metadata.
+    #[serde(default = "defaultMetadataPrefix")]
+    pub metadata_prefix: String,
+    /// Version of the KV secret engine, 1 or 2. KV v1 has no
+    /// `data_prefix` and `metadata_prefix` in paths, and no versions,
+    /// so only listing, reading, writing, and deleting work with it.
+    #[serde(default = "defaultKVVersion")]
+    pub kv_version: u8,
 }
 
 impl Config
@@ -96,9 +489,29 @@ impl Config
     pub fn fromfile(path: &Path) -> Result<Self, Error>
     {
         let content = std::fs::read_to_string(path).map_err(
-            |_| rterr!("Failed to read config file"))?;
-        toml::from_str(&content).map_err(
-            |e| rterr!("Failed to parse config file: {}", e))
+            |_| error!(ConfigError, "Failed to read config file"))?;
+        let mut conf: Self = toml::from_str(&content).map_err(|e| error!(
+            ConfigError, "Failed to parse config file: {}",
+            describeParseError(&content, &e)))?;
+        if conf.end_point.is_empty()
+        {
+            conf.end_point = vaultAddr().ok_or_else(
+                || error!(ConfigError, "Failed to parse config file: missing \
+                                       field `end_point`, and VAULT_ADDR is \
+                                       not set"))?;
+        }
+        conf.end_point = normalizeEndPoint(&conf.end_point);
+        if conf.username.is_empty() && !conf.username_from_token
+        {
+            return Err(error!(ConfigError, "Failed to parse config file: \
+                              missing field `username`"));
+        }
+        if conf.kv_version != 1 && conf.kv_version != 2
+        {
+            return Err(error!(ConfigError, "Invalid kv_version: {}",
+                              conf.kv_version));
+        }
+        Ok(conf)
     }
 
     pub fn clipboardProg(&self) -> Option<String>
@@ -113,11 +526,64 @@ impl Config
             {
                 "linux" => Some(String::from("xclip")),
                 "macos" => Some(String::from("pbcopy")),
+                "windows" => Some(String::from("clip")),
                 _ => None,
             }
         }
     }
 
+    /// Load the config of the profile `name` in `profiles`.
+    pub fn loadProfile(&self, name: &str) -> Result<Config, Error>
+    {
+        let file = self.profiles.get(name).ok_or_else(
+            || error!(ConfigError, "No profile {}", name))?;
+        let mut conf = Config::fromfile(Path::new(file)).map_err(
+            |e| error!(ConfigError, "Profile {}: {}", name, e.message()))?;
+        if conf.cache_path.is_none()
+        {
+            conf.cache_path = findConfigDir().map(|mut p| {
+                p.push(format!("runtime-{}.{}", name, conf.runtime_format));
+                p.to_string_lossy().into_owned()
+            });
+        }
+        Ok(conf)
+    }
+
+    /// The programs and arguments that print the content of each
+    /// selection in `clipboard_selection`, if known for the clipboard
+    /// program.
+    pub fn clipboardPasteCommands(&self) ->
+        Option<Vec<(String, Vec<&'static str>)>>
+    {
+        let prog = self.clipboardProg()?;
+        self.clipboardSelections().ok()?.iter()
+            .map(|s| pasteCommand(&prog, s)).collect()
+    }
+
+    fn clipboardSelections(&self) -> Result<&'static [&'static str], Error>
+    {
+        match self.clipboard_selection.as_str()
+        {
+            "clipboard" => Ok(&["clipboard"]),
+            "primary" => Ok(&["primary"]),
+            "both" => Ok(&["clipboard", "primary"]),
+            s => Err(error!(ConfigError, "Invalid clipboard_selection: {}", s)),
+        }
+    }
+
+    /// The arguments to run the clipboard program with, one list for
+    /// each selection in `clipboard_selection`.
+    pub fn clipboardArgs(&self) -> Result<Vec<Vec<&'static str>>, Error>
+    {
+        let selections = self.clipboardSelections()?;
+        let prog = self.clipboardProg().unwrap_or_default();
+        let name = Path::new(&prog).file_stem()
+            .and_then(|n| n.to_str()).unwrap_or("");
+        selections.iter().map(|s| selectionArgs(name, s).ok_or_else(
+            || error!(ConfigError, "{} does not support the {} selection",
+                      prog, s))).collect()
+    }
+
     /// The username in lowercase. The userpass authentication in
     /// Vault automatically lowercase this; however this is also used
     /// to construct URIs. Vault treats URI in a case-sensitive
@@ -128,6 +594,56 @@ impl Config
         self.username.to_lowercase()
     }
 
+    /// Path of the KV mount, without surrounding slashes.
+    pub fn mount(&self) -> &str
+    {
+        self.mount.as_deref().map_or("passwords", |m| m.trim_matches('/'))
+    }
+
+    /// The number of the first choice in menus.
+    pub fn firstSelection(&self) -> usize
+    {
+        if self.one_based_selection { 1 } else { 0 }
+    }
+
+    /// The JWT to login with for the `jwt` and `oidc` auth methods.
+    pub fn jwt(&self) -> Result<String, Error>
+    {
+        if let Some(jwt) = &self.jwt
+        {
+            return Ok(jwt.clone());
+        }
+        if let Ok(jwt) = env::var("VH_JWT")
+        {
+            if !jwt.is_empty()
+            {
+                return Ok(jwt);
+            }
+        }
+        if let Some(file) = &self.jwt_file
+        {
+            return std::fs::read_to_string(file).map(|s| s.trim().to_owned())
+                .map_err(|_| error!(ConfigError, "Failed to read {}", file));
+        }
+        Err(error!(ConfigError, "No JWT. Use --jwt, VH_JWT, or jwt_file"))
+    }
+
+    /// The secret ID of the AppRole, if one is configured.
+    pub fn approleSecretID(&self) -> Result<Option<String>, Error>
+    {
+        if let Some(id) = &self.approle_secret_id
+        {
+            return Ok(Some(id.clone()));
+        }
+        if let Some(file) = &self.approle_secret_id_file
+        {
+            return std::fs::read_to_string(file)
+                .map(|s| Some(s.trim().to_owned()))
+                .map_err(|_| error!(ConfigError, "Failed to read {}", file));
+        }
+        Ok(None)
+    }
+
     /// Return the path of the runtime info file. Return what the path
     /// should be if the files does not exist. Return None if the path
     /// cannot be determined.
@@ -139,7 +655,7 @@ impl Config
         }
         else
         {
-            findRuntimeInfoFile()
+            findRuntimeInfoFile(&self.runtime_format)
         }
     }
 }
@@ -150,13 +666,102 @@ impl Default for Config
     {
         Self {
             ca_certs: Vec::new(),
+            trust_system_roots: true,
             end_point: String::from("https://localhost/"),
+            mount: None,
             username: String::from("metrowind"),
+            username_from_token: false,
             clipboard_prog: None,
+            clipboard_selection: defaultClipboardSelection(),
+            clipboard_required: false,
+            askpass: None,
+            auth_method: defaultAuthMethod(),
+            jwt_role: None,
+            approle_role_id: None,
+            approle_secret_id: None,
+            approle_secret_id_file: None,
+            jwt_file: None,
+            oidc_callback_address: defaultOIDCCallbackAddress(),
+            oidc_callback_port: defaultOIDCCallbackPort(),
+            oidc_timeout_secs: defaultOIDCTimeout(),
+            jwt: None,
+            token: None,
+            token_type: None,
+            reveal_hook: None,
             cache_path: None,
+            prefer_cache: false,
+            runtime_format: defaultRuntimeFormat(),
             local_xml: None,
             gpg_user: None,
             xml_export_period: 86400,
+            metrics_file: None,
+            profiles: IndexMap::new(),
+            audit_log: None,
+            export_diff_file: None,
+            verify_export: false,
+            read_only: false,
+            json_field: defaultJSONField(),
+            username_field: defaultUsernameField(),
+            totp_field: defaultTOTPField(),
+            rotation_warn_days: defaultRotationWarnDays(),
+            one_based_selection: false,
+            password_length: defaultPasswordLength(),
+            redact_fields: Vec::new(),
+            client_encrypted_fields: Vec::new(),
+            client_key: None,
+            strict_allowed_fields: Vec::new(),
+            quiet: false,
+            trace: false,
+            clipboard_clear_secs: defaultClipboardClear(),
+            max_response_bytes: defaultMaxResponseBytes(),
+            concurrency: defaultConcurrency(),
+            pool_max_idle_per_host: None,
+            follow_standby_redirect: false,
+            http2_prior_knowledge: false,
+            data_prefix: defaultDataPrefix(),
+            metadata_prefix: defaultMetadataPrefix(),
+            kv_version: defaultKVVersion(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// The error from loading a config file with `content`, or an
+    /// empty string if it loads.
+    fn loadError(content: &str) -> String
+    {
+        static FILES: std::sync::atomic::AtomicUsize =
+            std::sync::atomic::AtomicUsize::new(0);
+        let file = std::env::temp_dir().join(format!(
+            "vault-hunter-test-config-{}-{}.toml", std::process::id(),
+            FILES.fetch_add(1, std::sync::atomic::Ordering::SeqCst)));
+        std::fs::write(&file, content).unwrap();
+        let result = Config::fromfile(&file);
+        std::fs::remove_file(&file).unwrap();
+        result.err().map(|e| e.to_string()).unwrap_or_default()
+    }
+
+    #[test]
+    fn malformedConfigErrorsSayWhere()
+    {
+        let end_point = "end_point = \"https://vault/\"\n";
+        assert_eq!(loadError(&format!("{}username = \"me\"\n", end_point)), "");
+        let err = loadError(&format!("{}username = 42\n", end_point));
+        assert!(err.starts_with("Config error: Failed to parse config file: \
+                                 invalid value for `username` at line 2:"),
+                "{}", err);
+        let err = loadError(&format!("{}username = \"me\n", end_point));
+        assert!(err.contains("line 2"), "{}", err);
+        let err = loadError(&format!("{}username = \"me\"\n[x\n", end_point));
+        assert!(err.contains("line 3"), "{}", err);
+        let err = loadError(end_point);
+        assert!(err.contains("missing field `username`"), "{}", err);
+        let err = loadError(&format!("{}username = \"me\"\nkv_version = 3\n",
+                                     end_point));
+        assert!(err.contains("Invalid kv_version: 3"), "{}", err);
+    }
+}
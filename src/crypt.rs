@@ -0,0 +1,103 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::config::Config;
+use crate::error::Error;
+
+/// How an ASCII-armored age file starts. Values that start like this
+/// are taken as already encrypted.
+const AGE_ARMOR_HEADER: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+
+fn isEncrypted(value: &str) -> bool
+{
+    value.trim_start().starts_with(AGE_ARMOR_HEADER)
+}
+
+fn clientKey(conf: &Config) -> Result<&str, Error>
+{
+    let key = conf.client_key.as_deref().ok_or_else(
+        || error!(ConfigError, "client_encrypted_fields is set, but there \
+                               is no client_key"))?;
+    if !std::path::Path::new(key).exists()
+    {
+        return Err(error!(ConfigError, "Client key {} is not available",
+                          key));
+    }
+    Ok(key)
+}
+
+/// Run age with `args` on `input`, and return its output.
+fn runAge(args: &[&str], input: &[u8]) -> Result<Vec<u8>, Error>
+{
+    let mut proc = Command::new("age").args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().map_err(|e| rterr!("Failed to run age: {}", e))?;
+    // Feed stdin from another thread, otherwise age could block on a
+    // full stdout pipe while we are still writing.
+    let mut proc_stdin = proc.stdin.take().unwrap();
+    let input = input.to_vec();
+    let feeder = std::thread::spawn(move || proc_stdin.write_all(&input));
+    let output = proc.wait_with_output().map_err(
+        |e| rterr!("Failed to run age: {}", e))?;
+    feeder.join().map_err(|_| rterr!("Failed to input to age"))?
+        .map_err(|e| rterr!("Failed to input to age: {}", e))?;
+    if !output.status.success()
+    {
+        return Err(rterr!("age failed: {}",
+                          String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+/// Encrypt the fields in `client_encrypted_fields` with the client
+/// key, for writing to Vault. Values that are already encrypted are
+/// kept.
+pub fn encryptFields(data: &serde_json::Map<String, serde_json::Value>,
+                     conf: &Config) ->
+    Result<serde_json::Map<String, serde_json::Value>, Error>
+{
+    let mut result = data.clone();
+    for (key, value) in result.iter_mut()
+    {
+        let text = match value
+        {
+            serde_json::Value::String(s) if !isEncrypted(s) => s,
+            _ => continue,
+        };
+        if !conf.client_encrypted_fields.contains(key)
+        {
+            continue;
+        }
+        let out = runAge(&["--encrypt", "--armor", "-i", clientKey(conf)?],
+                         text.as_bytes()).map_err(
+            |e| rterr!("Failed to encrypt {}: {}", key, e.message()))?;
+        *text = String::from_utf8_lossy(&out).into_owned();
+    }
+    Ok(result)
+}
+
+/// Decrypt the fields in `client_encrypted_fields` with the client
+/// key. Values that are not encrypted, like those written before
+/// the field was listed, are kept.
+pub fn decryptFields(data: &mut serde_json::Map<String, serde_json::Value>,
+                     conf: &Config) -> Result<(), Error>
+{
+    for (key, value) in data.iter_mut()
+    {
+        let text = match value
+        {
+            serde_json::Value::String(s) if isEncrypted(s) => s,
+            _ => continue,
+        };
+        if !conf.client_encrypted_fields.contains(key)
+        {
+            continue;
+        }
+        let out = runAge(&["--decrypt", "-i", clientKey(conf)?],
+                         text.as_bytes()).map_err(
+            |e| rterr!("Failed to decrypt {}: {}", key, e.message()))?;
+        *text = String::from_utf8(out).map_err(
+            |_| rterr!("Decrypted {} is not UTF-8", key))?;
+    }
+    Ok(())
+}
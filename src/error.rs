@@ -24,11 +24,49 @@ macro_rules! rterr
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error
 {
     VaultError(String),
     HTTPError(String),
     RuntimeError(String),
+    /// Login failed, or the token is not allowed to do something.
+    AuthError(String),
+    /// The requested entry does not exist.
+    NotFoundError(String),
+    ConfigError(String),
+    /// More than one entry matches where exactly one is needed.
+    AmbiguousError(String),
+}
+
+impl Error
+{
+    /// Name of the error variant.
+    pub fn kind(&self) -> &'static str
+    {
+        match self
+        {
+            Error::VaultError(_) => "VaultError",
+            Error::HTTPError(_) => "HTTPError",
+            Error::RuntimeError(_) => "RuntimeError",
+            Error::AuthError(_) => "AuthError",
+            Error::NotFoundError(_) => "NotFoundError",
+            Error::ConfigError(_) => "ConfigError",
+            Error::AmbiguousError(_) => "AmbiguousError",
+        }
+    }
+
+    /// The error message, without the kind.
+    pub fn message(&self) -> &str
+    {
+        match self
+        {
+            Error::VaultError(msg) | Error::HTTPError(msg)
+                | Error::RuntimeError(msg) | Error::AuthError(msg)
+                | Error::NotFoundError(msg) | Error::ConfigError(msg)
+                | Error::AmbiguousError(msg) => msg,
+        }
+    }
 }
 
 impl fmt::Display for Error
@@ -40,6 +78,10 @@ impl fmt::Display for Error
             Error::VaultError(msg) => write!(f, "Vault error: {}", msg),
             Error::HTTPError(msg) => write!(f, "HTTP error: {}", msg),
             Error::RuntimeError(msg) => write!(f, "Runtime error: {}", msg),
+            Error::AuthError(msg) => write!(f, "Auth error: {}", msg),
+            Error::NotFoundError(msg) => write!(f, "Not found: {}", msg),
+            Error::ConfigError(msg) => write!(f, "Config error: {}", msg),
+            Error::AmbiguousError(msg) => write!(f, "Ambiguous: {}", msg),
         }
     }
 }
@@ -48,3 +90,65 @@ impl StdError for Error
 {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {None}
 }
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_GENERIC: i32 = 1;
+pub const EXIT_AUTH: i32 = 2;
+pub const EXIT_NOT_FOUND: i32 = 3;
+pub const EXIT_NETWORK: i32 = 4;
+pub const EXIT_CONFIG: i32 = 5;
+pub const EXIT_AMBIGUOUS: i32 = 6;
+/// Used by `--has-field` when the entry exists without the field.
+pub const EXIT_NO_FIELD: i32 = 7;
+
+/// Description of the exit codes, for the help text.
+pub const EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    Success
+    1    Generic error
+    2    Authentication failure or permission denied
+    3    Entry not found
+    4    Network error
+    5    Configuration error
+    6    More than one entry matches
+    7    The entry has no such field (--has-field)";
+
+/// The process exit code to use when exiting because of `err`.
+pub fn exitCodeFor(err: &Error) -> i32
+{
+    match err
+    {
+        Error::VaultError(_) | Error::RuntimeError(_) => EXIT_GENERIC,
+        Error::AuthError(_) => EXIT_AUTH,
+        Error::NotFoundError(_) => EXIT_NOT_FOUND,
+        Error::HTTPError(_) => EXIT_NETWORK,
+        Error::ConfigError(_) => EXIT_CONFIG,
+        Error::AmbiguousError(_) => EXIT_AMBIGUOUS,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn eachErrorHasItsExitCode()
+    {
+        let msg = String::from("x");
+        let codes = [(Error::VaultError(msg.clone()), EXIT_GENERIC),
+                     (Error::RuntimeError(msg.clone()), EXIT_GENERIC),
+                     (Error::AuthError(msg.clone()), EXIT_AUTH),
+                     (Error::NotFoundError(msg.clone()), EXIT_NOT_FOUND),
+                     (Error::HTTPError(msg.clone()), EXIT_NETWORK),
+                     (Error::ConfigError(msg.clone()), EXIT_CONFIG),
+                     (Error::AmbiguousError(msg), EXIT_AMBIGUOUS)];
+        for (err, code) in &codes
+        {
+            assert_eq!(exitCodeFor(err), *code, "{}", err.kind());
+            // The help text lists every code that is used.
+            assert!(EXIT_CODES_HELP.contains(&format!("\n    {}  ", code)));
+        }
+        assert_ne!(exitCodeFor(&Error::VaultError(String::new())),
+                   EXIT_SUCCESS);
+    }
+}
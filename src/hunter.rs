@@ -1,18 +1,45 @@
-use std::io::{stdin,stdout,Write};
+use std::collections::HashMap;
+use std::io::{stdin,stderr,BufRead,Write};
 use std::process::Command;
 use chrono::prelude::*;
+use indexmap::IndexMap;
 
-use crate::vault_client::{Client, KeyOrDir, Path};
+use crate::vault_client::{rawEntry, stringifyEntry, Client, KeyOrDir, Matcher,
+                          Path, SearchTarget, StringMap, VersionMetadata};
 use crate::error::Error;
 use crate::config::Config;
 use crate::runtime_info::{getRuntimeInfo, setRuntimeInfo};
+use crate::xml;
+use crate::audit;
+use crate::output::Spinner;
+use crate::source::{SecretSource, XmlSource};
 
-fn promptForInput(prompt: &str) -> Result<String, Error>
+/// Prompt for a line of input. Return None at the end of input.
+fn promptForLine(prompt: &str) -> Result<Option<String>, Error>
+{
+    // Prompts go to stderr, so that they are seen even if stdout is
+    // piped.
+    eprint!("{}", prompt);
+    let _ = stderr().flush();
+    let line = readLine(&mut stdin().lock())?;
+    if line.is_none()
+    {
+        eprintln!();
+    }
+    Ok(line)
+}
+
+/// Read a line from `input` without the line ending. Return None at
+/// the end of input.
+fn readLine(input: &mut dyn BufRead) -> Result<Option<String>, Error>
 {
     let mut s = String::new();
-    print!("{}", prompt);
-    let _ = stdout().flush();
-    stdin().read_line(&mut s).map_err(|_| rterr!("Failed to read line"))?;
+    let n = input.read_line(&mut s)
+        .map_err(|_| rterr!("Failed to read line"))?;
+    if n == 0
+    {
+        return Ok(None);
+    }
 
     if let Some('\n')=s.chars().next_back()
     {
@@ -22,32 +49,88 @@ fn promptForInput(prompt: &str) -> Result<String, Error>
     {
         s.pop();
     }
-    Ok(s)
+    Ok(Some(s))
+}
+
+/// Prompt for a line of input. The end of input is an error.
+fn promptForInput(prompt: &str) -> Result<String, Error>
+{
+    promptForLine(prompt)?.ok_or_else(|| rterr!("Unexpected end of input"))
+}
+
+/// Like `promptForInput`, but do not echo the input. Use this for
+/// anything sensitive.
+fn promptForSecret(prompt: &str) -> Result<String, Error>
+{
+    rpassword::prompt_password(prompt).map_err(
+        |_| rterr!("Failed to read input"))
+}
+
+/// Ask the user a yes/no question. Anything other than “y” or “yes”
+/// is a no.
+fn confirm(question: &str) -> Result<bool, Error>
+{
+    let answer = promptForLine(&format!("{} [y/N] ", question))?
+        .unwrap_or_default();
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
 }
 
+/// Copy `content` to the clipboard. Return false if there is no
+/// usable clipboard program, unless `clipboard_required` is set, in
+/// which case that is an error.
 fn clipboardCopy(content: &str, conf: &Config) -> Result<bool, Error>
 {
-    if conf.clipboardProg().is_none()
+    let clipboard_prog = if let Some(prog) = conf.clipboardProg()
     {
-        return Ok(false);
+        prog
+    }
+    else if conf.clipboard_required
+    {
+        return Err(rterr!("No clipboard program for this OS, and \
+                           clipboard_required is set"));
     }
+    else
+    {
+        return Ok(false);
+    };
 
-    let clipboard_prog = conf.clipboardProg().unwrap();
-    if Command::new(&clipboard_prog)
+    let selections = conf.clipboardArgs()?;
+    if let Err(e) = Command::new(&clipboard_prog)
         .stdout(std::process::Stdio::null())
         .stderr(std::process::Stdio::null())
         .stdin(std::process::Stdio::null())
-        .spawn().is_err()
+        .spawn()
     {
-        return Ok(false);
+        if !conf.clipboard_required
+        {
+            return Ok(false);
+        }
+        if e.kind() == std::io::ErrorKind::NotFound
+        {
+            return Err(rterr!("Clipboard program {} is not installed, and \
+                               clipboard_required is set", clipboard_prog));
+        }
+        return Err(rterr!("Failed to run clipboard program {}: {}",
+                          clipboard_prog, e));
+    }
+
+    for args in selections
+    {
+        runClipboardProg(&clipboard_prog, &args, content)?;
     }
+    Ok(true)
+}
 
-    let mut proc = Command::new(clipboard_prog)
+/// Pipe `content` to the clipboard program `prog` run with `args`.
+fn runClipboardProg(prog: &str, args: &[&str], content: &str) ->
+    Result<(), Error>
+{
+    let mut proc = Command::new(prog)
+        .args(args)
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::null())
         .stderr(std::process::Stdio::null())
-        .spawn().map_err(|e| rterr!("Failed to run {}: {}",
-                                    conf.clipboard_prog.as_ref().unwrap(), e))?;
+        .spawn().map_err(|e| rterr!("Failed to run {}: {}", prog, e))?;
     {
         let proc_stdin = proc.stdin.as_mut().unwrap();
         proc_stdin.write_all(content.as_bytes()).map_err(
@@ -57,7 +140,7 @@ fn clipboardCopy(content: &str, conf: &Config) -> Result<bool, Error>
         |_| rterr!("Clipboard program failed to run"))?;
     if status.success()
     {
-        Ok(true)
+        Ok(())
     }
     else
     {
@@ -73,141 +156,398 @@ fn clipboardCopy(content: &str, conf: &Config) -> Result<bool, Error>
     }
 }
 
-async fn revealPath(client: &Client<'_>, path: String, conf: &Config) -> Result<(), Error>
+/// Options for revealing an entry.
+#[derive(Default)]
+pub struct RevealOptions
 {
-    let data = client.get(&path).await?;
-    for (key, value) in &data
+    /// How to print the entry.
+    pub format: OutputFormat,
+    /// Include secret fields in output formats that would otherwise
+    /// leave them out.
+    pub reveal: bool,
+    /// Copy the whole entry to the clipboard as a JSON object.
+    pub copy_json: bool,
+    /// Copy the fields to the clipboard one by one.
+    pub fill: bool,
+    /// Show the fields in `redact_fields` too.
+    pub show_all: bool,
+    /// Offer a menu to copy any of the fields to the clipboard.
+    pub copy_all: bool,
+    /// Copy this field instead of the password.
+    pub field: Option<String>,
+    /// Print only the value of the field, without copying.
+    pub raw: bool,
+    /// Fail if the entry has fields other than the field to reveal
+    /// and the allowed ones in the config.
+    pub strict: bool,
+    /// Write the field, or the whole entry as JSON if no field is
+    /// given, to this file.
+    pub output_file: Option<String>,
+    /// Overwrite the output file if it exists.
+    pub force: bool,
+}
+
+/// Write `content` to `file`, readable only by the user on Unix.
+/// Refuse to overwrite an existing file unless `force`.
+fn writeSecretFile(file: &str, content: &[u8], force: bool) ->
+    Result<(), Error>
+{
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    if force
     {
-        if key != "Password"
-        {
-            println!("{}: {}", key, value);
-        }
+        options.create(true).truncate(true);
     }
-
-    if let Some(password) = data.get("Password")
+    else
     {
-        if clipboardCopy(password, conf)?
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut f = options.open(file).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::AlreadyExists
         {
-            println!("Password copied to clipboard.");
-            std::thread::sleep(std::time::Duration::from_secs(1));
+            rterr!("{} already exists. Use --force to overwrite", file)
         }
         else
         {
-            println!("Password: {}", password);
+            rterr!("Failed to open {}: {}", file, e)
         }
+    })?;
+    // The mode only applies to new files.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        f.set_permissions(std::fs::Permissions::from_mode(0o600)).map_err(
+            |e| rterr!("Failed to set permissions on {}: {}", file, e))?;
     }
-    Ok(())
+    f.write_all(content).map_err(
+        |e| rterr!("Failed to write {}: {}", file, e))
 }
 
-/// Search for an entry and reveal the key-value pair in a way that is
-/// appropriate to the end-user.
-pub async fn searchReveal(client: &Client<'_>, pattern: &str, conf: &Config) ->
-    Result<(), Error>
+/// How to print a revealed entry.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat
 {
-    let paths = client.search(pattern).await?;
-    if paths.is_empty()
+    /// Print the fields and copy the password.
+    #[default]
+    Text,
+    /// Print shell `export` statements.
+    Env,
+}
+
+impl std::str::FromStr for OutputFormat
+{
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err>
     {
-        return Ok(());
+        match s
+        {
+            "text" => Ok(OutputFormat::Text),
+            "env" => Ok(OutputFormat::Env),
+            _ => Err(rterr!("Invalid output format: {}", s)),
+        }
+    }
+}
+
+/// Field with the expiry date of an entry, in RFC 3339 or as a plain
+/// date like `2024-12-31`.
+const EXPIRES_FIELD: &str = "__expires";
+
+/// Whether the field `key` is information about the entry for this
+/// program, rather than part of the credential. These start with
+/// `__`, e.g. `__expires`.
+fn isMetaField(key: &str) -> bool
+{
+    key.starts_with("__")
+}
+
+/// Parse an expiry date. A plain date means the start of that day in
+/// UTC.
+fn parseExpiry(value: &str) -> Option<DateTime<Utc>>
+{
+    if let Ok(t) = DateTime::parse_from_rfc3339(value)
+    {
+        return Some(t.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0)).map(|t| t.and_utc())
+}
+
+/// Warn if the expiry date `expires` is past, or within
+/// `rotation_warn_days`.
+fn warnExpiry(expires: &str, conf: &Config)
+{
+    let t = if let Some(t) = parseExpiry(expires)
+    {
+        t
+    }
+    else
+    {
+        eprintln!("Warning: invalid {} value: {}", EXPIRES_FIELD, expires);
+        return;
+    };
+    let days = (t - Utc::now()).num_days();
+    if t < Utc::now()
+    {
+        eprintln!("Warning: this credential expired {} days ago — consider \
+                   rotating.", -days);
     }
-    if paths.len() == 1
+    else if days < conf.rotation_warn_days
     {
-        return revealPath(client, paths[0].to_string(), conf).await;
+        eprintln!("Warning: this credential expires in {} days — consider \
+                   rotating.", days);
     }
+}
 
-    // Multiple search result
-    for i in 0..paths.len()
+/// Decode base32 as in RFC 4648. Padding, spaces, and case are
+/// ignored.
+fn decodeBase32(s: &str) -> Option<Vec<u8>>
+{
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in s.trim_end_matches('=').bytes().filter(|c| *c != b' ')
     {
-        println!("{}. {}", i, paths[i]);
+        let value = ALPHABET.iter()
+            .position(|a| *a == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8
+        {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
     }
-    println!("");
-    let choice = loop
+    if bytes.is_empty()
     {
-        if let Ok(choice) = promptForInput("Which entry? ")?.parse::<usize>()
+        return None;
+    }
+    Some(bytes)
+}
+
+/// The TOTP code (RFC 6238) at `time` for `secret`, which is in
+/// base32 or an `otpauth://` URI. A bare secret uses the defaults:
+/// SHA-1, 6 digits, and a period of 30 seconds. A URI can set
+/// `algorithm` (SHA1, SHA256, or SHA512), `digits` (6 to 8), and
+/// `period`. Other values are an error rather than a wrong code.
+fn totpCode(secret: &str, time: DateTime<Utc>) -> Result<String, Error>
+{
+    let mut algorithm = &ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY;
+    let mut digits: u32 = 6;
+    let mut period: i64 = 30;
+    let secret = if secret.starts_with("otpauth://")
+    {
+        let url = reqwest::Url::parse(secret).map_err(
+            |e| rterr!("Invalid otpauth URI: {}", e))?;
+        let mut seed = None;
+        for (key, value) in url.query_pairs()
         {
-            if choice < paths.len()
+            match key.as_ref()
             {
-                break choice;
+                "secret" => seed = Some(value.into_owned()),
+                "algorithm" => algorithm = match value.to_uppercase().as_str()
+                {
+                    "SHA1" => &ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+                    "SHA256" => &ring::hmac::HMAC_SHA256,
+                    "SHA512" => &ring::hmac::HMAC_SHA512,
+                    _ => return Err(rterr!("Unsupported TOTP algorithm: {}",
+                                           value)),
+                },
+                "digits" => digits = value.parse().ok()
+                    .filter(|d| (6..=8).contains(d)).ok_or_else(
+                        || rterr!("Unsupported TOTP digits: {}", value))?,
+                "period" => period = value.parse().ok().filter(|p| *p > 0)
+                    .ok_or_else(
+                        || rterr!("Unsupported TOTP period: {}", value))?,
+                _ => {},
             }
         }
-        println!("Invalid input");
+        seed.ok_or_else(|| rterr!("No secret in the otpauth URI"))?
+    }
+    else
+    {
+        secret.to_owned()
     };
-    revealPath(client, paths[choice].to_string(), conf).await
+    let key = decodeBase32(&secret).ok_or_else(
+        || rterr!("Invalid base32 TOTP secret"))?;
+    let counter = (time.timestamp() / period) as u64;
+    let key = ring::hmac::Key::new(*algorithm, &key);
+    let mac = ring::hmac::sign(&key, &counter.to_be_bytes());
+    let mac = mac.as_ref();
+    // Dynamic truncation
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([mac[offset], mac[offset + 1],
+                                   mac[offset + 2], mac[offset + 3]])
+        & 0x7fff_ffff;
+    Ok(format!("{:01$}", code % 10u32.pow(digits), digits as usize))
+}
+
+/// Whether the field `key` holds a secret that should not be shown
+/// unless asked for. The TOTP seed is as secret as the password.
+fn isSecret(key: &str, conf: &Config) -> bool
+{
+    key == "Password" || key == conf.totp_field
+}
+
+/// Quote `value` for a POSIX shell. Inside single quotes nothing is
+/// special except the single quote itself, which is written as `'\''`
+/// (close the quote, an escaped quote, reopen). So the result is
+/// always one literal word, whatever the value contains.
+fn shellQuote(value: &str) -> String
+{
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Print the fields of `data` as shell `export` statements. Secret
+/// fields are left out unless `reveal` is true.
+fn printEnv(data: &StringMap, reveal: bool, conf: &Config)
+{
+    for (key, value) in data
+    {
+        if reveal || !isSecret(key, conf)
+        {
+            println!("export {}={}", hookEnvName(key), shellQuote(value));
+        }
+    }
+}
+
+/// The content of each selection in `clipboard_selection`, or None
+/// if it cannot be read.
+fn clipboardRead(conf: &Config) -> Option<Vec<String>>
+{
+    conf.clipboardPasteCommands()?.into_iter().map(|(prog, args)| {
+        let output = Command::new(prog).args(args)
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .output().ok()?;
+        if !output.status.success()
+        {
+            return None;
+        }
+        String::from_utf8(output.stdout).ok()
+    }).collect()
 }
 
-async fn exportEntry(client: &Client<'_>,
-                     writer: &mut quick_xml::Writer<std::io::Cursor<Vec<u8>>>,
-                     path: &str) -> Result<(), Error>
+/// Copy `content` to the clipboard, or print it if the clipboard is
+/// not available. `name` describes the content to the user.
+async fn copyOrPrint(content: &str, name: &str, conf: &Config) ->
+    Result<(), Error>
 {
-    let data = client.get(&path).await?;
-    writer.create_element("entry").with_attribute(("path", path))
-        .write_inner_content(|writer| {
-            for (key, value) in &data
+    if clipboardCopy(content, conf)?
+    {
+        info!(conf, "{} copied to clipboard.", name);
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        if conf.clipboard_clear_secs > 0
+        {
+            info!(conf, "Clearing clipboard in {} seconds...",
+                  conf.clipboard_clear_secs);
+            tokio::time::sleep(std::time::Duration::from_secs(
+                conf.clipboard_clear_secs)).await;
+            if clipboardRead(conf)
+                .is_none_or(|c| c.iter().all(|s| s == content))
             {
-                writer.create_element("kv").write_inner_content(|kv_writer| {
-                    kv_writer.create_element("key").write_text_content(
-                        quick_xml::events::BytesText::from_plain_str(key))?;
-                    kv_writer.create_element("value").write_text_content(
-                        quick_xml::events::BytesText::from_plain_str(value))?;
-                    Ok(())
-                })?;
+                clipboardCopy("", conf)?;
+                info!(conf, "Clipboard cleared.");
             }
-            Ok(())
-        }).map_err(|e| rterr!("Failed to write entry: {}", e))?;
-    return Ok(());
+            else
+            {
+                info!(conf, "Clipboard changed since, not clearing.");
+            }
+        }
+    }
+    else
+    {
+        println!("{}: {}", name, content);
+    }
+    Ok(())
 }
 
-/// Export passwords as an XML string.
-async fn passwordsToXML(client: &Client<'_>) -> Result<Vec<u8>, Error>
+/// Copy each field of `data` to the clipboard in order, waiting for
+/// the user between fields. Clear the clipboard at the end.
+fn fillFields(data: &StringMap, conf: &Config) -> Result<(), Error>
 {
-    let mut to_search: Vec<Path> = vec![Path::new(),];
-    let mut writer = quick_xml::Writer::new_with_indent(
-        std::io::Cursor::new(Vec::new()), 32, 2);
+    for (key, value) in data
+    {
+        if !clipboardCopy(value, conf)?
+        {
+            return Err(rterr!("Clipboard is not available"));
+        }
+        promptForInput(&format!(
+            "{} copied to clipboard. Press Enter to continue...", key))?;
+    }
+    clipboardCopy("", conf)?;
+    info!(conf, "Clipboard cleared.");
+    Ok(())
+}
 
-    // Breath-first search through all entries.
-    while !to_search.is_empty()
+/// Let the user copy any field of `data` to the clipboard by its
+/// number, until `q` or an empty input. Clear the clipboard at the
+/// end.
+fn copyFieldsMenu(data: &StringMap, conf: &Config) -> Result<(), Error>
+{
+    let keys: Vec<&String> = data.keys().collect();
+    let first = conf.firstSelection();
+    for (i, key) in keys.iter().enumerate()
+    {
+        eprintln!("{}. {}", i + first, key);
+    }
+    loop
     {
-        let mut next_to_search: Vec<Path> = Vec::default();
-        for path in &to_search
+        let input = promptForLine("Copy which field? (q to finish) ")?
+            .unwrap_or_default();
+        if input.trim().is_empty() || input.trim() == "q"
+        {
+            break;
+        }
+        match input.trim().parse::<usize>().ok()
+            .and_then(|i| i.checked_sub(first)).and_then(|i| keys.get(i))
         {
-            for item in client.list(&path.to_string()).await?
+            Some(key) =>
             {
-                match item
+                if !clipboardCopy(&data[*key], conf)?
                 {
-                    KeyOrDir::Key(name) =>
-                    {
-                        let full_path = path.pushed(&name);
-                        exportEntry(client, &mut writer,
-                                    &full_path.to_string()).await?;
-                    },
-                    KeyOrDir::Dir(name) =>
-                    {
-                        next_to_search.push(path.pushed(&name));
-                    },
+                    return Err(rterr!("Clipboard is not available"));
                 }
-            }
+                info!(conf, "{} copied to clipboard.", key);
+            },
+            None => eprintln!("Invalid input"),
         }
-        to_search = next_to_search;
     }
+    clipboardCopy("", conf)?;
+    info!(conf, "Clipboard cleared.");
+    Ok(())
+}
 
-    Ok(writer.into_inner().into_inner())
+/// Name of the environment variable passed to the reveal hook for
+/// field `key`. Characters other than ASCII letters and digits are
+/// replaced with underscores.
+fn hookEnvName(key: &str) -> String
+{
+    let name: String = key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    format!("VH_FIELD_{}", name)
 }
 
-/// Encrypt bytes with GPG to a file.
-fn gpgEncrypt(data: Vec<u8>, filename: &str, user: &str) -> Result<(), Error>
+/// Run the configured reveal hook for the entry at `path`. The fields
+/// are passed only as environment variables, never as arguments,
+/// because arguments are visible to other users on the system.
+fn runRevealHook(hook: &str, path: &str, data: &StringMap) ->
+    Result<(), Error>
 {
-    let mut proc = Command::new("gpg").args(
-        ["--yes", "-r", user , "--encrypt", "-a", "-o",])
-        .arg(filename).arg("-")
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .spawn().map_err(|e| rterr!("Failed to run GPG: {}", e))?;
+    let mut cmd = Command::new(hook);
+    cmd.env("VH_PATH", path);
+    for (key, value) in data
     {
-        let proc_stdin = proc.stdin.as_mut().unwrap();
-        proc_stdin.write_all(&data).map_err(
-            |e| rterr!("Failed to input to GPG: {}", e))?;
+        cmd.env(hookEnvName(key), value);
     }
-    let status = proc.wait().map_err(|_| rterr!("GPG failed to run"))?;
+    let status = cmd.status().map_err(
+        |e| rterr!("Failed to run reveal hook {}: {}", hook, e))?;
     if status.success()
     {
         Ok(())
@@ -222,40 +562,2280 @@ fn gpgEncrypt(data: Vec<u8>, filename: &str, user: &str) -> Result<(), Error>
         {
             String::from("??")
         };
-        Err(rterr!("GPG failed with code: {}", code_str))
+        Err(rterr!("Reveal hook failed with code: {}", code_str))
     }
 }
 
-pub async fn exportPasswords(client: &Client<'_>, conf: &Config) ->
+/// Check that `data` has no fields other than `field` and the
+/// allowed ones in the config.
+fn checkStrict(data: &StringMap, field: &str, conf: &Config) ->
     Result<(), Error>
 {
-    let last_xml_time: DateTime<Utc> = if let Some(t_str) =
-        getRuntimeInfo("last_xml_export_time", conf)?
+    let extra: Vec<&str> = data.keys()
+        .filter(|k| *k != field && !conf.strict_allowed_fields.contains(k))
+        .map(|k| k.as_str()).collect();
+    if extra.is_empty()
     {
-        t_str.parse().unwrap()
+        Ok(())
     }
     else
     {
-        chrono::MIN_DATETIME
-    };
+        Err(rterr!("Entry has unexpected fields: {}", extra.join(", ")))
+    }
+}
 
-    let now = Utc::now();
-    if (now - last_xml_time).num_seconds() < conf.xml_export_period
+/// Prefix of the fields that hold an attachment. A file is stored
+/// base64-encoded and split across `__chunk_0`, `__chunk_1`, and so
+/// on, which are joined in order. Vault limits the size of a request
+/// to 32 MiB by default, so attachments should stay well below that;
+/// chunks of up to 1 MiB keep each value manageable.
+const CHUNK_PREFIX: &str = "__chunk_";
+
+/// Join and decode the attachment of the entry at `path`, if it has
+/// one. Fail if a chunk is missing or numbered twice.
+fn assembleChunks(data: &StringMap, path: &str) ->
+    Result<Option<Vec<u8>>, Error>
+{
+    use base64::Engine;
+    let mut chunks: Vec<(usize, &str)> = Vec::new();
+    for (key, value) in data
     {
-        return Ok(())
+        if let Some(index) = key.strip_prefix(CHUNK_PREFIX)
+        {
+            let index = index.parse().map_err(
+                |_| rterr!("Invalid attachment chunk {} in {}", key, path))?;
+            chunks.push((index, value));
+        }
     }
+    if chunks.is_empty()
+    {
+        return Ok(None);
+    }
+    chunks.sort_by_key(|(i, _)| *i);
+    let mut encoded = String::new();
+    for (expected, (index, chunk)) in chunks.iter().enumerate()
+    {
+        if *index != expected
+        {
+            return Err(rterr!("Attachment chunk {} of {} is {}", expected,
+                              path, if *index < expected { "duplicated" }
+                              else { "missing" }));
+        }
+        encoded.extend(chunk.chars().filter(|c| !c.is_whitespace()));
+    }
+    base64::engine::general_purpose::STANDARD.decode(encoded).map(Some)
+        .map_err(|e| rterr!("Invalid attachment in {}: {}", path, e))
+}
+
+/// Field with the path of another entry that this one stands for.
+const ALIAS_FIELD: &str = "__alias";
+/// Maximal number of aliases followed in a row.
+const MAX_ALIAS_DEPTH: usize = 8;
 
-    let gpg_user = if let Some(u) = &conf.gpg_user
+/// Retrieve the entry at `path`, following `__alias` fields to the
+/// entry they point to. Return the path and data of the final entry.
+/// Fail on a cycle, on too many aliases in a row, or on an alias to
+/// a missing entry.
+async fn resolveAlias(source: &dyn SecretSource, mut path: String,
+                      conf: &Config) ->
+    Result<(String, serde_json::Map<String, serde_json::Value>), Error>
+{
+    let mut visited: Vec<String> = Vec::new();
+    loop
     {
-        u
+        let data = match source.getRaw(&path).await
+        {
+            Err(Error::NotFoundError(_)) if !visited.is_empty() =>
+                return Err(error!(NotFoundError, "Alias {} -> {} points to no \
+                                  entry", visited.last().unwrap(), path)),
+            result => result?,
+        };
+        let target = if let Some(t) = data.get(ALIAS_FIELD)
+            .and_then(|t| t.as_str())
+        {
+            t.to_owned()
+        }
+        else
+        {
+            return Ok((path, data));
+        };
+        visited.push(path);
+        if visited.contains(&target)
+        {
+            return Err(rterr!("Alias cycle: {} -> {}", visited.join(" -> "),
+                              target));
+        }
+        if visited.len() > MAX_ALIAS_DEPTH
+        {
+            return Err(rterr!("Too many aliases in a row from {}",
+                              visited[0]));
+        }
+        info!(conf, "Following alias {} -> {}", visited.last().unwrap(),
+              target);
+        path = target;
     }
-    else
+}
+
+async fn revealPath(source: &dyn SecretSource, path: String, conf: &Config,
+                    opts: &RevealOptions) -> Result<(), Error>
+{
+    let (path, raw_data) = resolveAlias(source, path, conf).await?;
+    // The JSON outputs keep the values as they are stored.
+    let data = stringifyEntry(raw_data.clone());
+    if let Some(hook) = &conf.reveal_hook
     {
-        return Err(rterr!("No GPG user provided."));
-    };
+        runRevealHook(hook, &path, &data)?;
+    }
+    if let Some(expires) = data.get(EXPIRES_FIELD)
+    {
+        warnExpiry(expires, conf);
+    }
+    let attachment = assembleChunks(&data, &path)?;
+    if attachment.is_some() && opts.output_file.is_none()
+    {
+        info!(conf, "{} has an attachment. Use --output-file to save it.",
+              path);
+    }
+    // Unless asked for explicitly, fields like `__expires` are not
+    // part of the entry for the user.
+    let data: StringMap = data.into_iter()
+        .filter(|(k, _)| !isMetaField(k) || opts.field.as_ref() == Some(k))
+        .collect();
+    let field = opts.field.as_deref().unwrap_or("Password");
+    if opts.strict
+    {
+        checkStrict(&data, field, conf)?;
+    }
+    if let Some(file) = &opts.output_file
+    {
+        if let (Some(content), None) = (&attachment, &opts.field)
+        {
+            writeSecretFile(file, content, opts.force)?;
+            info!(conf, "Attachment written to {}.", file);
+            return Ok(());
+        }
+        let content = if opts.field.is_some()
+        {
+            data.get(field).cloned().ok_or_else(
+                || error!(NotFoundError, "No field {} in {}", field, path))?
+        }
+        else
+        {
+            serde_json::to_string_pretty(&raw_data).map_err(
+                |e| rterr!("Failed to serialize entry: {}", e))?
+        };
+        writeSecretFile(file, content.as_bytes(), opts.force)?;
+        info!(conf, "Written to {}.", file);
+        return Ok(());
+    }
+    if opts.raw || opts.field.is_some()
+    {
+        let value = data.get(field).ok_or_else(
+            || error!(NotFoundError, "No field {} in {}", field, path))?;
+        if opts.raw
+        {
+            println!("{}", value);
+            return Ok(());
+        }
+        return copyOrPrint(value, field, conf).await;
+    }
+    if opts.format == OutputFormat::Env
+    {
+        printEnv(&data, opts.reveal, conf);
+        return Ok(());
+    }
+    if opts.copy_json
+    {
+        let json = serde_json::to_string(&raw_data).map_err(
+            |e| rterr!("Failed to serialize entry: {}", e))?;
+        return copyOrPrint(&json, "Entry", conf).await;
+    }
+    if opts.fill
+    {
+        return fillFields(&data, conf);
+    }
+    if opts.copy_all
+    {
+        return copyFieldsMenu(&data, conf);
+    }
 
-    println!("Exporting XML...");
-    let xml = passwordsToXML(client).await?;
-    gpgEncrypt(xml, &conf.local_xml.as_ref().unwrap(), gpg_user)?;
-    setRuntimeInfo("last_xml_export_time", Some(&now.to_rfc3339()), conf)
+    // The password is copied below, and the TOTP seed is shown as a
+    // code. Both can be had with --field.
+    for (key, value) in &data
+    {
+        if isSecret(key, conf)
+        {
+            continue;
+        }
+        if !opts.show_all && conf.redact_fields.contains(key)
+        {
+            println!("{}: ****", key);
+        }
+        else
+        {
+            println!("{}: {}", key, value);
+        }
+    }
+
+    if let Some(secret) = data.get(&conf.totp_field)
+    {
+        // A broken secret should not keep the password from being
+        // revealed.
+        match totpCode(secret, Utc::now())
+        {
+            Ok(code) => println!("TOTP code: {}", code),
+            Err(e) => eprintln!("Warning: {}: {}", conf.totp_field,
+                                e.message()),
+        }
+    }
+    if let Some(password) = data.get("Password")
+    {
+        copyOrPrint(password, "Password", conf).await?;
+    }
+    Ok(())
+}
+
+/// Print the items in the directory at `path`.
+async fn listDir(source: &dyn SecretSource, path: &str) -> Result<(), Error>
+{
+    for item in source.list(path).await?
+    {
+        match item
+        {
+            KeyOrDir::Key(name) => println!("{}", name),
+            KeyOrDir::Dir(name) => println!("{}/", name),
+        }
+    }
+    Ok(())
+}
+
+/// Reveal a search result. Keys are revealed as entries, and
+/// directories are listed.
+async fn revealItem(source: &dyn SecretSource, item: &KeyOrDir, conf: &Config,
+                    opts: &RevealOptions) -> Result<(), Error>
+{
+    match item
+    {
+        KeyOrDir::Key(path) => audit::record(
+            conf, "reveal", Some(path),
+            revealPath(source, path.clone(), conf, opts).await),
+        KeyOrDir::Dir(path) => listDir(source, path).await,
+    }
+}
+
+/// Describe the named capture groups of a regex matcher on `path`,
+/// like “ (service=github, account=me)”. Return an empty string if
+/// there is nothing to show.
+fn describeCaptures(matcher: &Matcher, path: &str) -> String
+{
+    let re = if let Matcher::Regex(re) = matcher
+    {
+        re
+    }
+    else
+    {
+        return String::new();
+    };
+    let caps = if let Some(caps) = re.captures(path)
+    {
+        caps
+    }
+    else
+    {
+        return String::new();
+    };
+    let groups: Vec<String> = re.capture_names().flatten()
+        .filter_map(|name| caps.name(name)
+                    .map(|m| format!("{}={}", name, m.as_str())))
+        .collect();
+    if groups.is_empty()
+    {
+        String::new()
+    }
+    else
+    {
+        format!(" ({})", groups.join(", "))
+    }
+}
+
+/// Order of search results.
+#[derive(Clone, Copy)]
+pub enum SortOrder
+{
+    /// By full path
+    Path,
+    /// By the last component of the path
+    Name,
+    /// Most recently updated first. This needs to retrieve the
+    /// metadata of every result.
+    Modified,
+}
+
+impl std::str::FromStr for SortOrder
+{
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s
+        {
+            "path" => Ok(SortOrder::Path),
+            "name" => Ok(SortOrder::Name),
+            "modified" => Ok(SortOrder::Modified),
+            _ => Err(rterr!("Invalid sort order: {}", s)),
+        }
+    }
+}
+
+pub struct SearchOptions
+{
+    pub target: SearchTarget,
+    pub sort: SortOrder,
+    /// Also match the values of the fields. This retrieves every
+    /// entry.
+    pub values: bool,
+}
+
+/// Find the entries with field values matching `matcher`. Return the
+/// names of the matching fields by path.
+async fn searchValues(source: &dyn SecretSource, matcher: &Matcher) ->
+    Result<IndexMap<String, Vec<String>>, Error>
+{
+    let mut result = IndexMap::new();
+    for path in source.keys().await?
+    {
+        let fields: Vec<String> = source.get(&path.to_string()).await?
+            .into_iter().filter(|(_, v)| matcher.matchesValue(v))
+            .map(|(k, _)| k).collect();
+        if !fields.is_empty()
+        {
+            result.insert(path.to_string(), fields);
+        }
+    }
+    Ok(result)
+}
+
+fn leafName(path: &str) -> &str
+{
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Sort search results, whose items hold full paths.
+async fn sortItems(source: &dyn SecretSource, items: &mut [KeyOrDir],
+                   order: SortOrder) -> Result<(), Error>
+{
+    match order
+    {
+        SortOrder::Path => items.sort_by(|a, b| a.name().cmp(b.name())),
+        SortOrder::Name => items.sort_by(
+            |a, b| (leafName(a.name()), a.name())
+                .cmp(&(leafName(b.name()), b.name()))),
+        SortOrder::Modified =>
+        {
+            let mut times: HashMap<String, DateTime<Utc>> = HashMap::new();
+            for item in items.iter()
+            {
+                if let KeyOrDir::Key(path) = item
+                {
+                    if let Some(t) = source.updatedTime(path).await?
+                    {
+                        times.insert(path.clone(), t);
+                    }
+                }
+            }
+            // Directories have no time, and end up last.
+            items.sort_by(|a, b| times.get(b.name()).cmp(&times.get(a.name())));
+        },
+    }
+    Ok(())
+}
+
+/// Reveal the entry at `path`, or list it if it is empty or ends with
+/// a slash, without searching.
+pub async fn revealExact(source: &dyn SecretSource, path: &str,
+                         conf: &Config, opts: &RevealOptions) ->
+    Result<(), Error>
+{
+    let item = if path.is_empty() || path.ends_with('/')
+    {
+        KeyOrDir::Dir(path.trim_end_matches('/').to_owned())
+    }
+    else
+    {
+        KeyOrDir::Key(path.to_owned())
+    };
+    revealItem(source, &item, conf, opts).await
+}
+
+/// Search for an entry and reveal the key-value pair in a way that is
+/// appropriate to the end-user.
+pub async fn searchReveal(source: &dyn SecretSource, matcher: &Matcher,
+                          search_opts: &SearchOptions, conf: &Config,
+                          opts: &RevealOptions) -> Result<(), Error>
+{
+    let spinner = Spinner::start("Searching...", conf);
+    let found = source.search(matcher, search_opts.target).await?;
+    let value_matches = if search_opts.values
+    {
+        searchValues(source, matcher).await?
+    }
+    else
+    {
+        IndexMap::new()
+    };
+    drop(spinner);
+    if found.is_empty() && value_matches.is_empty()
+    {
+        return Err(error!(NotFoundError, "No entry matches {}", matcher));
+    }
+    // The items hold full paths here.
+    let mut items: Vec<KeyOrDir> = found.dirs.iter()
+        .map(|p| KeyOrDir::Dir(p.to_string()))
+        .chain(found.keys.iter().map(|p| KeyOrDir::Key(p.to_string())))
+        .collect();
+    for path in value_matches.keys()
+    {
+        if !found.keys.iter().any(|p| p.to_string() == *path)
+        {
+            items.push(KeyOrDir::Key(path.clone()));
+        }
+    }
+    if items.len() == 1
+    {
+        return revealItem(source, &items[0], conf, opts).await;
+    }
+
+    // Multiple search result
+    sortItems(source, &mut items, search_opts.sort).await?;
+    let first = conf.firstSelection();
+    for (i, item) in items.iter().enumerate()
+    {
+        let i = i + first;
+        match item
+        {
+            KeyOrDir::Key(path) => eprintln!(
+                "{}. {}{}{}", i, path, describeCaptures(matcher, path),
+                value_matches.get(path).map(|fields| format!(
+                    " [matched in {}]", fields.join(", ")))
+                .unwrap_or_default()),
+            KeyOrDir::Dir(path) => eprintln!(
+                "{}. {}/{}", i, path, describeCaptures(matcher, path)),
+        }
+    }
+    eprintln!();
+    let choice = loop
+    {
+        // An empty line, “q”, or the end of input cancels.
+        let input = promptForLine("Which entry? (Enter to cancel) ")?
+            .unwrap_or_default();
+        let input = input.trim();
+        if input.is_empty() || input == "q"
+        {
+            info!(conf, "Cancelled.");
+            return Ok(());
+        }
+        if let Some(choice) = input.parse::<usize>().ok()
+            .and_then(|c| c.checked_sub(first))
+        {
+            if choice < items.len()
+            {
+                break choice;
+            }
+        }
+        eprintln!("Invalid input");
+    };
+    revealItem(source, &items[choice], conf, opts).await
+}
+
+/// Load the local XML export to search without Vault. The export is
+/// as old as the last export, so warn about that.
+pub fn loadOffline(conf: &Config) -> Result<XmlSource, Error>
+{
+    let file = conf.local_xml.as_ref().ok_or_else(
+        || error!(ConfigError, "Offline mode needs local_xml in the config"))?;
+    let modified: DateTime<Local> = std::fs::metadata(file)
+        .and_then(|m| m.modified()).map_err(
+            |e| rterr!("Failed to read {}: {}", file, e))?.into();
+    info!(conf, "Offline: using the export from {}. Later changes in Vault \
+                 are not included.", modified.format("%Y-%m-%d %H:%M"));
+    Ok(XmlSource::new(readExportFile(file)?))
+}
+
+/// Print the metadata of the entry at `path`, and how many versions
+/// the mount keeps.
+pub async fn printMetadata(client: &Client<'_>, path: &str) ->
+    Result<(), Error>
+{
+    let meta = client.metadata(path).await?;
+    println!("Current version: {}", meta.current_version);
+    println!("Created: {}", meta.created_time);
+    println!("Updated: {}", meta.updated_time);
+    println!("Versions:");
+    let mut versions: Vec<(u64, &VersionMetadata)> = meta.versions.iter()
+        .filter_map(|(v, m)| v.parse().ok().map(|v| (v, m))).collect();
+    versions.sort_by_key(|(v, _)| *v);
+    for (v, m) in versions
+    {
+        let state = if m.destroyed
+        {
+            String::from(", destroyed")
+        }
+        else if !m.deletion_time.is_empty()
+        {
+            format!(", deleted {}", m.deletion_time)
+        }
+        else
+        {
+            String::new()
+        };
+        println!("  {}: created {}{}", v, m.created_time, state);
+    }
+
+    let mount = client.mountConfig().await?;
+    let max_versions = match mount.max_versions { 0 => 10, n => n };
+    println!("The mount keeps at most {} versions.", max_versions);
+    if mount.cas_required
+    {
+        println!("The mount requires check-and-set for writes.");
+    }
+    Ok(())
+}
+
+/// Print the field names in `keys`, the subkeys structure of an
+/// entry. Nested fields are joined to their parent by a dot.
+fn printSubkeys(keys: &serde_json::Map<String, serde_json::Value>,
+                prefix: &str)
+{
+    for (key, value) in keys
+    {
+        let name = format!("{}{}", prefix, key);
+        println!("{}", name);
+        if let serde_json::Value::Object(sub) = value
+        {
+            printSubkeys(sub, &format!("{}.", name));
+        }
+    }
+}
+
+/// Print the field names of the entry at `path`, without retrieving
+/// any value.
+pub async fn printKeys(client: &Client<'_>, path: &str) -> Result<(), Error>
+{
+    printSubkeys(&client.subkeys(path).await?, "");
+    Ok(())
+}
+
+/// Print the field `field` of the entry at `path`, which holds a JSON
+/// document, pretty-printed. Print the value as it is if it is not
+/// valid JSON.
+pub async fn printJSONField(client: &Client<'_>, path: &str, field: &str,
+                            conf: &Config) -> Result<(), Error>
+{
+    let data = client.get(path).await?;
+    let value = data.get(field).ok_or_else(
+        || error!(NotFoundError, "No field {} in {}", field, path))?;
+    match serde_json::from_str::<serde_json::Value>(value)
+    {
+        Ok(doc) => println!("{}", serde_json::to_string_pretty(&doc).unwrap()),
+        Err(e) =>
+        {
+            info!(conf, "{} is not valid JSON ({}):", field, e);
+            println!("{}", value);
+        },
+    }
+    Ok(())
+}
+
+/// Copy the entry at `from` to `to`. Refuse to overwrite an existing
+/// entry at `to` unless `force` is true. The read and the write are
+/// audited separately, as `copy-read` and `copy-write`.
+pub async fn copyEntry(client: &Client<'_>, from: &str, to: &str, force: bool,
+                       conf: &Config) -> Result<(), Error>
+{
+    let result = client.getRaw(from).await;
+    let data = audit::record(conf, "copy-read", Some(from), result)?;
+    let result = writeCopy(client, to, &data, force, conf).await;
+    audit::record(conf, "copy-write", Some(to), result)?;
+    info!(conf, "Copied {} to {}.", from, to);
+    Ok(())
+}
+
+async fn writeCopy(client: &Client<'_>, to: &str,
+                   data: &serde_json::Map<String, serde_json::Value>,
+                   force: bool, conf: &Config) -> Result<(), Error>
+{
+    // Writing with the current version as check-and-set also guards
+    // against the destination changing between the check and the
+    // write. KV v1 has no versions, so there is only the check.
+    let (exists, version) = if conf.kv_version == 1
+    {
+        match client.getRaw(to).await
+        {
+            Ok(_) => (true, None),
+            Err(Error::NotFoundError(_)) => (false, None),
+            Err(e) => return Err(e),
+        }
+    }
+    else
+    {
+        let version = client.currentVersion(to).await?;
+        (version.is_some(), Some(version.unwrap_or(0)))
+    };
+    if exists && !force
+    {
+        return Err(rterr!("{} already exists. Use --force to overwrite", to));
+    }
+    client.put(to, data, version).await
+}
+
+/// Write the entry at `path` back unchanged, creating a new version
+/// with a fresh update time. Unless `yes` is true, confirm with the
+/// user first.
+pub async fn touch(client: &Client<'_>, path: &str, yes: bool, conf: &Config)
+                   -> Result<(), Error>
+{
+    let (data, version) = client.getVersioned(path).await?;
+    if !yes && !confirm(&format!("Create a new version of {}?", path))?
+    {
+        info!(conf, "Cancelled.");
+        return Ok(());
+    }
+    client.put(path, &data, version).await?;
+    info!(conf, "Touched {}.", path);
+    Ok(())
+}
+
+/// Read a field value from stdin without echoing it if stdin is a
+/// terminal. Otherwise the whole input is the value, without the
+/// final newline.
+fn readValue(key: &str) -> Result<String, Error>
+{
+    use std::io::{IsTerminal, Read};
+    if stdin().is_terminal()
+    {
+        return promptForSecret(&format!("{}: ", key));
+    }
+    let mut value = String::new();
+    stdin().read_to_string(&mut value).map_err(
+        |e| rterr!("Failed to read the value of {}: {}", key, e))?;
+    if value.ends_with('\n')
+    {
+        value.pop();
+        if value.ends_with('\r')
+        {
+            value.pop();
+        }
+    }
+    Ok(value)
+}
+
+/// Set the field of the entry at `path` in `assignment`, given as
+/// `KEY=VALUE`, and write the entry back as a new version with the
+/// other fields unchanged. The value is read from stdin if it is
+/// `-`. The entry has to exist.
+pub async fn replaceField(client: &Client<'_>, path: &str, assignment: &str,
+                          conf: &Config) -> Result<(), Error>
+{
+    let (key, value) = assignment.split_once('=').ok_or_else(
+        || rterr!("Expecting KEY=VALUE, got {}", assignment))?;
+    if key.is_empty()
+    {
+        return Err(rterr!("Empty field name in {}", assignment));
+    }
+    // The version of the data read is the check-and-set version, so
+    // that a write in between is not overwritten.
+    let (mut data, version) = client.getVersioned(path).await?;
+    let value = if value == "-"
+    {
+        readValue(key)?
+    }
+    else
+    {
+        value.to_owned()
+    };
+    let existed = data.insert(key.to_owned(), serde_json::Value::String(value))
+        .is_some();
+    client.put(path, &data, version).await?;
+    info!(conf, "{} field {} of {}.", if existed { "Replaced" }
+          else { "Added" }, key, path);
+    Ok(())
+}
+
+/// Characters of generated passwords.
+const PASSWORD_CHARS: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789\
+      !@#$%^&*-_=+";
+
+/// Generate a random password of `length` characters.
+fn generatePassword(length: usize) -> Result<String, Error>
+{
+    let mut password = String::with_capacity(length);
+    // Reject bytes beyond the largest multiple of the number of
+    // characters, so that every character is equally likely.
+    let limit = 256 - 256 % PASSWORD_CHARS.len();
+    let mut buf = [0u8; 64];
+    while password.len() < length
+    {
+        getrandom::fill(&mut buf).map_err(
+            |e| rterr!("Failed to get random bytes: {}", e))?;
+        for b in buf.iter().filter(|b| (**b as usize) < limit)
+            .take(length - password.len())
+        {
+            password.push(PASSWORD_CHARS[*b as usize % PASSWORD_CHARS.len()]
+                          as char);
+        }
+    }
+    Ok(password)
+}
+
+/// Replace the password of the entry at `path` with a generated one,
+/// keeping the other fields, and copy the new password. The time is
+/// recorded in `__rotated`. The old password stays in the previous
+/// version. Unless `yes` is true, confirm with the user first.
+pub async fn rotate(client: &Client<'_>, path: &str, yes: bool, conf: &Config)
+                    -> Result<(), Error>
+{
+    // The version of the data read is the check-and-set version, so
+    // that a write in between is not overwritten.
+    let (mut data, version) = client.getVersioned(path).await?;
+    if !yes && !confirm(&format!("Replace the password of {}?", path))?
+    {
+        info!(conf, "Cancelled.");
+        return Ok(());
+    }
+    let password = generatePassword(conf.password_length)?;
+    data.insert(String::from("Password"),
+                serde_json::Value::String(password.clone()));
+    data.insert(String::from("__rotated"),
+                serde_json::Value::String(Utc::now().to_rfc3339()));
+    client.put(path, &data, version).await?;
+    info!(conf, "Rotated the password of {}.", path);
+    copyOrPrint(&password, "Password", conf).await
+}
+
+/// Delete all the entries that match `matcher`. Unless `yes` is
+/// true, confirm with the user first.
+pub async fn prune(client: &Client<'_>, matcher: &Matcher, yes: bool,
+                   conf: &Config) -> Result<(), Error>
+{
+    // Fail before listing and asking, not after every delete.
+    client.checkWritable("prune")?;
+    let paths = client.search(matcher, SearchTarget::Key).await?.keys;
+    if paths.is_empty()
+    {
+        return Err(error!(NotFoundError, "No entry matches {}", matcher));
+    }
+    for path in &paths
+    {
+        eprintln!("{}", path);
+    }
+    if !yes && !confirm(&format!("Permanently delete these {} entries?",
+                                 paths.len()))?
+    {
+        info!(conf, "Cancelled.");
+        return Ok(());
+    }
+
+    let mut failures = 0;
+    for path in &paths
+    {
+        let path = path.to_string();
+        let result = client.delete(&path).await;
+        if let Err(e) = audit::record(conf, "delete", Some(&path), result)
+        {
+            eprintln!("{}", e);
+            failures += 1;
+        }
+    }
+    info!(conf, "Deleted {} entries.", paths.len() - failures);
+    if failures > 0
+    {
+        return Err(rterr!("Failed to delete {} entries", failures));
+    }
+    Ok(())
+}
+
+/// List the entries whose `__expires` date is within `days`, or
+/// already past, soonest first. With `show_missing`, also list the
+/// entries without a valid expiry date.
+pub async fn printExpiring(client: &Client<'_>, days: i64, show_missing: bool)
+                           -> Result<(), Error>
+{
+    let mut paths: Vec<Path> = Vec::new();
+    client.walk(&Path::new(), |path, item| if let KeyOrDir::Key(_) = item
+    {
+        paths.push(path.clone());
+    }).await?;
+
+    let deadline = Utc::now() + chrono::Duration::days(days);
+    let mut expiring: Vec<(DateTime<Utc>, String)> = Vec::new();
+    let mut missing: Vec<String> = Vec::new();
+    for path in paths
+    {
+        let data = client.get(&path.to_string()).await?;
+        match data.get(EXPIRES_FIELD).and_then(|v| parseExpiry(v))
+        {
+            Some(t) if t <= deadline => expiring.push((t, path.to_string())),
+            Some(_) => {},
+            None => missing.push(path.to_string()),
+        }
+    }
+    expiring.sort();
+    for (t, path) in &expiring
+    {
+        let note = if *t < Utc::now() { " (expired)" } else { "" };
+        println!("{}  {}{}", t.format("%Y-%m-%d"), path, note);
+    }
+    if show_missing && !missing.is_empty()
+    {
+        println!();
+        println!("Without a valid {}:", EXPIRES_FIELD);
+        for path in &missing
+        {
+            println!("  {}", path);
+        }
+    }
+    Ok(())
+}
+
+/// Print the result of a self-test step, and return whether it
+/// passed.
+fn reportStep<T>(step: &str, result: &Result<T, Error>) -> bool
+{
+    match result
+    {
+        Ok(_) => println!("{}: ok", step),
+        Err(e) => println!("{}: FAILED: {}", step, e),
+    }
+    result.is_ok()
+}
+
+/// Write a scratch entry, read it back and compare, then delete it.
+/// The entry is deleted even if reading it back fails.
+pub async fn selfTest(client: &Client<'_>) -> Result<(), Error>
+{
+    let path = format!("vault-hunter-self-test/{}",
+                       Utc::now().format("%Y%m%dT%H%M%S"));
+    let mut data = StringMap::new();
+    data.insert(String::from("Password"), generatePassword(24)?);
+    data.insert(String::from("Note"), String::from(
+        "Written by vault-hunter --self-test. Safe to delete."));
+    println!("Scratch entry: {}", path);
+
+    let written = client.put(&path, &rawEntry(&data), Some(0)).await;
+    let mut passed = reportStep("Write", &written);
+    if passed
+    {
+        let read = client.get(&path).await;
+        passed = reportStep("Read", &read);
+        if let Ok(read) = read
+        {
+            let same = if read == data
+            {
+                Ok(())
+            }
+            else
+            {
+                Err(rterr!("Read entry differs from the written one"))
+            };
+            passed = reportStep("Compare", &same);
+        }
+        passed &= reportStep("Delete", &client.delete(&path).await);
+    }
+    if passed
+    {
+        println!("PASS");
+        Ok(())
+    }
+    else
+    {
+        println!("FAIL");
+        Err(rterr!("Self-test failed"))
+    }
+}
+
+/// Delete the runtime info file, which has the cached token and the
+/// time of the last export. The token is not revoked. Unless `yes`
+/// is true, confirm with the user first.
+pub fn deleteCache(conf: &Config, yes: bool) -> Result<(), Error>
+{
+    let path = conf.runtimeInfoPath().ok_or_else(
+        || rterr!("Cannot determine the path of the runtime info file"))?;
+    if !path.exists()
+    {
+        info!(conf, "No runtime info at {}.", path.display());
+        return Ok(());
+    }
+    if !yes && !confirm(&format!("Delete {}?", path.display()))?
+    {
+        info!(conf, "Cancelled.");
+        return Ok(());
+    }
+    std::fs::remove_file(&path).map_err(
+        |e| rterr!("Failed to delete {}: {}", path.display(), e))?;
+    info!(conf, "Deleted {}.", path.display());
+    Ok(())
+}
+
+/// Print the paths of the config file and the runtime info file, and
+/// whether each exists.
+pub fn printPaths(conf: &Config)
+{
+    let paths = [("Config file", crate::config::configFilePath()),
+                 ("Runtime info", conf.runtimeInfoPath())];
+    for (name, path) in paths
+    {
+        match path
+        {
+            Some(p) => println!("{}: {} ({})", name, p.display(),
+                                if p.exists() { "exists" }
+                                else { "does not exist" }),
+            None => println!("{}: cannot be determined", name),
+        }
+    }
+}
+
+/// Format a number of seconds like “1h 2m 3s”.
+fn formatDuration(secs: u64) -> String
+{
+    let (h, m, s) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    if h > 0
+    {
+        format!("{}h {}m {}s", h, m, s)
+    }
+    else if m > 0
+    {
+        format!("{}m {}s", m, s)
+    }
+    else
+    {
+        format!("{}s", s)
+    }
+}
+
+/// Log in, prompting for the password if needed, and report how long
+/// the cached token is valid. The report is the result, so it goes to
+/// stdout even if quiet.
+pub async fn loginOnly(client: &mut Client<'_>) -> Result<(), Error>
+{
+    client.login().await?;
+    let info = client.lookupToken().await?;
+    match info["data"]["ttl"].as_u64()
+    {
+        // Root tokens have no TTL.
+        Some(0) | None => println!("Logged in. Token does not expire."),
+        Some(ttl) => println!("Logged in. Token expires in {}.",
+                              formatDuration(ttl)),
+    }
+    Ok(())
+}
+
+/// Search for the entries matching `matcher` without prompting, and
+/// print the result as JSON. If exactly one entry matches, print
+///
+///     {"path": "<path>", "fields": {"<name>": "<value>", ...}}
+///
+/// with the secret fields only if `reveal` is true. If several match,
+/// print
+///
+///     {"matches": ["<path>", ...]}
+///
+/// and return an AmbiguousError.
+pub async fn query(client: &Client<'_>, matcher: &Matcher, reveal: bool,
+                   conf: &Config) -> Result<(), Error>
+{
+    let paths = client.search(matcher, SearchTarget::Key).await?.keys;
+    match paths.len()
+    {
+        0 => Err(error!(NotFoundError, "No entry matches {}", matcher)),
+        1 =>
+        {
+            let path = paths[0].to_string();
+            let result = client.getRaw(&path).await;
+            let fields: serde_json::Map<String, serde_json::Value> =
+                audit::record(conf, "query", Some(&path), result)?.into_iter()
+                .filter(|(k, _)| reveal || !isSecret(k, conf)).collect();
+            println!("{}", serde_json::json!({"path": path, "fields": fields}));
+            Ok(())
+        },
+        n =>
+        {
+            let matches: Vec<String> =
+                paths.iter().map(|p| p.to_string()).collect();
+            println!("{}", serde_json::json!({"matches": matches}));
+            Err(error!(AmbiguousError, "{} entries match {}", n, matcher))
+        },
+    }
+}
+
+/// Fetch the entries at `paths` in parallel, and print a JSON object
+/// keyed by path like
+///
+///     {"<path>": {"fields": {...}},
+///      "<path>": {"error": {"type": "NotFoundError", "message": ...}}}
+///
+/// Secret fields are left out unless `reveal`. A failed path does
+/// not stop the others; an error is returned at the end if any
+/// failed.
+pub async fn batchGet(client: &Client<'_>, paths: &[String], reveal: bool,
+                      conf: &Config) -> Result<(), Error>
+{
+    // The client limits how many of these are in flight.
+    let results = futures_util::future::join_all(
+        paths.iter().map(|p| client.get(p))).await;
+    let mut output = serde_json::Map::new();
+    let mut failures = 0;
+    for (path, result) in paths.iter().zip(results)
+    {
+        let value = match audit::record(conf, "batch-get", Some(path), result)
+        {
+            Ok(data) =>
+            {
+                let fields: StringMap = data.into_iter()
+                    .filter(|(k, _)| reveal || !isSecret(k, conf)).collect();
+                serde_json::json!({"fields": fields})
+            },
+            Err(e) =>
+            {
+                failures += 1;
+                serde_json::json!({"error": {"type": e.kind(),
+                                             "message": e.message()}})
+            },
+        };
+        output.insert(path.clone(), value);
+    }
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    if failures > 0
+    {
+        return Err(rterr!("Failed to get {} of {} entries", failures,
+                          paths.len()));
+    }
+    Ok(())
+}
+
+/// A piece of an export template.
+enum TemplatePiece
+{
+    Text(String),
+    Field(String),
+}
+
+/// Parse a template with `{field}` placeholders. `{{` and `}}` are
+/// literal braces, and `\n`, `\t`, and `\\` are a newline, a tab,
+/// and a backslash.
+fn parseTemplate(template: &str) -> Result<Vec<TemplatePiece>, Error>
+{
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next()
+    {
+        match c
+        {
+            '{' if chars.peek() == Some(&'{') =>
+            {
+                chars.next();
+                text.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') =>
+            {
+                chars.next();
+                text.push('}');
+            },
+            '{' =>
+            {
+                let name: String = chars.by_ref().take_while(|c| *c != '}')
+                    .collect();
+                if name.is_empty()
+                {
+                    return Err(rterr!("Empty or unclosed placeholder in \
+                                       template"));
+                }
+                pieces.push(TemplatePiece::Text(std::mem::take(&mut text)));
+                pieces.push(TemplatePiece::Field(name));
+            },
+            '}' => return Err(rterr!("Unmatched }} in template")),
+            '\\' => match chars.next()
+            {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some('\\') => text.push('\\'),
+                Some(other) =>
+                {
+                    text.push('\\');
+                    text.push(other);
+                },
+                None => text.push('\\'),
+            },
+            _ => text.push(c),
+        }
+    }
+    pieces.push(TemplatePiece::Text(text));
+    Ok(pieces)
+}
+
+/// Render `pieces` for the entry at `path`. `{path}` is the path of
+/// the entry. A missing field renders empty, or is an error if
+/// `strict`.
+fn renderTemplate(pieces: &[TemplatePiece], path: &str, data: &StringMap,
+                  strict: bool) -> Result<String, Error>
+{
+    let mut result = String::new();
+    for piece in pieces
+    {
+        match piece
+        {
+            TemplatePiece::Text(text) => result.push_str(text),
+            TemplatePiece::Field(name) if name == "path" =>
+                result.push_str(path),
+            TemplatePiece::Field(name) => match data.get(name)
+            {
+                Some(value) => result.push_str(value),
+                None if strict => return Err(error!(
+                    NotFoundError, "No field {} in {}", name, path)),
+                None => {},
+            },
+        }
+    }
+    Ok(result)
+}
+
+/// Print every entry, or the entries matching `matcher`, through
+/// `template`, sorted by path. This prints secrets if the template
+/// has them.
+pub async fn exportTemplate(source: &dyn SecretSource,
+                            matcher: Option<&Matcher>, template: &str,
+                            strict: bool, conf: &Config) -> Result<(), Error>
+{
+    let pieces = parseTemplate(template)?;
+    let paths = match matcher
+    {
+        Some(m) => source.search(m, SearchTarget::Key).await?.keys,
+        None => source.keys().await?,
+    };
+    let mut paths: Vec<String> = paths.iter().map(|p| p.to_string())
+        .collect();
+    paths.sort();
+    for path in &paths
+    {
+        let data = audit::record(conf, "export-template", Some(path),
+                                 source.get(path).await)?;
+        print!("{}", renderTemplate(&pieces, path, &data, strict)?);
+    }
+    Ok(())
+}
+
+/// Print a summary of the vault: numbers of entries and
+/// directories, and the maximal depth. With `detailed`, also fetch
+/// every entry to count the fields. Values are never printed.
+pub async fn printStats(client: &Client<'_>, detailed: bool, json: bool) ->
+    Result<(), Error>
+{
+    let all = client.search(&Matcher::substring(""), SearchTarget::Both)
+        .await?;
+    let max_depth = all.keys.iter().chain(all.dirs.iter())
+        .map(|p| p.depth()).max().unwrap_or(0);
+    let mut stats = serde_json::json!({
+        "entries": all.keys.len(),
+        "directories": all.dirs.len(),
+        "max_depth": max_depth,
+    });
+    if detailed
+    {
+        let mut field_count = 0;
+        let mut histogram: IndexMap<String, usize> = IndexMap::new();
+        for path in &all.keys
+        {
+            let data = client.get(&path.to_string()).await?;
+            field_count += data.len();
+            for key in data.keys()
+            {
+                *histogram.entry(key.clone()).or_insert(0) += 1;
+            }
+        }
+        histogram.sort_by(|k1, v1, k2, v2| v2.cmp(v1).then(k1.cmp(k2)));
+        let average = if all.keys.is_empty()
+        {
+            0.0
+        }
+        else
+        {
+            field_count as f64 / all.keys.len() as f64
+        };
+        stats["average_fields"] = serde_json::json!(average);
+        stats["fields"] = serde_json::json!(histogram);
+    }
+
+    if json
+    {
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        return Ok(());
+    }
+    println!("Entries: {}", stats["entries"]);
+    println!("Directories: {}", stats["directories"]);
+    println!("Max depth: {}", stats["max_depth"]);
+    if let Some(average) = stats["average_fields"].as_f64()
+    {
+        println!("Average fields per entry: {:.2}", average);
+    }
+    if let Some(fields) = stats["fields"].as_object()
+    {
+        println!("Field names:");
+        for (name, count) in fields
+        {
+            println!("  {}: {}", name, count);
+        }
+    }
+    Ok(())
+}
+
+/// Names of the fields that differ between two versions of an entry.
+#[derive(Default)]
+struct FieldDiff
+{
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+}
+
+impl FieldDiff
+{
+    fn new(old: &StringMap, new: &StringMap) -> Self
+    {
+        let mut diff = Self::default();
+        for (key, value) in new
+        {
+            match old.get(key)
+            {
+                None => diff.added.push(key.clone()),
+                Some(v) if v != value => diff.changed.push(key.clone()),
+                Some(_) => {},
+            }
+        }
+        diff.removed = old.keys().filter(|k| !new.contains_key(*k))
+            .cloned().collect();
+        diff
+    }
+
+    fn is_empty(&self) -> bool
+    {
+        self.added.is_empty() && self.removed.is_empty() &&
+            self.changed.is_empty()
+    }
+}
+
+impl std::fmt::Display for FieldDiff
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        let parts: Vec<String> = [("changed", &self.changed),
+                                  ("added", &self.added),
+                                  ("removed", &self.removed)].iter()
+            .filter(|(_, keys)| !keys.is_empty())
+            .map(|(what, keys)| format!("{}: {}", what, keys.join(", ")))
+            .collect();
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+/// Print a field-by-field comparison of the entries at `path_a` and
+/// `path_b`. Secret fields are only said to be the same or different.
+pub async fn compareEntries(client: &Client<'_>, path_a: &str, path_b: &str,
+                            conf: &Config) -> Result<(), Error>
+{
+    let a = client.get(path_a).await?;
+    let b = client.get(path_b).await?;
+    let mut keys: Vec<&String> = a.keys().collect();
+    keys.extend(b.keys().filter(|k| !a.contains_key(*k)));
+
+    let mut rows = vec![[String::from("FIELD"), String::from("STATUS"),
+                         String::from("A"), String::from("B")]];
+    for key in keys
+    {
+        let (va, vb) = (a.get(key), b.get(key));
+        let status = match (va, vb)
+        {
+            (Some(x), Some(y)) if x == y => "same",
+            (Some(_), Some(_)) => "differs",
+            (Some(_), None) => "only in a",
+            _ => "only in b",
+        };
+        let show = |v: Option<&String>| if isSecret(key, conf)
+        {
+            String::new()
+        }
+        else
+        {
+            v.cloned().unwrap_or_default()
+        };
+        rows.push([key.clone(), status.to_owned(), show(va), show(vb)]);
+    }
+
+    let mut widths = [0; 4];
+    for row in &rows
+    {
+        for (w, cell) in widths.iter_mut().zip(row)
+        {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    for row in &rows
+    {
+        let line = format!("{:w0$}  {:w1$}  {:w2$}  {}", row[0], row[1],
+                           row[2], row[3], w0 = widths[0], w1 = widths[1],
+                           w2 = widths[2]);
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
+
+/// Read an XML export from `file`, which may be encrypted with GPG.
+fn readExportFile(file: &str) -> Result<Vec<xml::Entry>, Error>
+{
+    let content = std::fs::read(file).map_err(
+        |e| rterr!("Failed to read {}: {}", file, e))?;
+    if content.starts_with(b"-----BEGIN PGP MESSAGE-----")
+    {
+        xml::parse(&gpgDecrypt(file)?)
+    }
+    else
+    {
+        xml::parse(&content)
+    }
+}
+
+/// Import the entries of an XML export in `file` into Vault. With
+/// `dry_run`, only print what would change. Field values are never
+/// printed.
+pub async fn importXML(client: &Client<'_>, file: &str, dry_run: bool,
+                       conf: &Config) -> Result<(), Error>
+{
+    let mut written = 0;
+    let mut unchanged = 0;
+    for entry in readExportFile(file)?
+    {
+        let (old, version) = match client.getVersioned(&entry.path).await
+        {
+            Ok((data, version)) => (Some(stringifyEntry(data)), version),
+            Err(Error::NotFoundError(_)) => (None, Some(0)),
+            Err(e) => return Err(e),
+        };
+        match &old
+        {
+            None => println!("create    {}", entry.path),
+            Some(data) =>
+            {
+                let diff = FieldDiff::new(data, &entry.data);
+                if diff.is_empty()
+                {
+                    unchanged += 1;
+                    if dry_run
+                    {
+                        println!("unchanged {}", entry.path);
+                    }
+                    continue;
+                }
+                println!("update    {} ({})", entry.path, diff);
+            },
+        }
+        if !dry_run
+        {
+            client.put(&entry.path, &rawEntry(&entry.data), version).await?;
+            written += 1;
+        }
+    }
+    if !dry_run
+    {
+        info!(conf, "Imported {} entries, {} unchanged.", written, unchanged);
+    }
+    Ok(())
+}
+
+/// Options for the XML export.
+#[derive(Default)]
+pub struct ExportOptions
+{
+    /// Write the XML without encrypting it.
+    pub plaintext: bool,
+    /// Include the version and creation time of each entry. This
+    /// needs an extra request per entry.
+    pub with_metadata: bool,
+    /// Merge into the existing local XML instead of replacing it.
+    /// Entries are matched by path, and the current one wins. Entries
+    /// no longer in Vault are kept.
+    pub merge: bool,
+    /// Decrypt and parse the local XML after exporting to check it.
+    pub verify: bool,
+    /// Report which paths were added, removed, or changed since the
+    /// previous local XML.
+    pub diff: bool,
+    /// Reuse the entries of the previous local XML whose version is
+    /// still the current one, instead of retrieving them again. This
+    /// needs an extra request per entry, but only for the metadata.
+    /// Entries without a known version, like in KV v1, are always
+    /// retrieved. See `reusableEntries` for which entries are
+    /// trusted.
+    pub dedupe: bool,
+}
+
+/// Describe the path-level changes from the `old` export to the `new`
+/// one, one line per path. Values are never included.
+fn diffExports(old: &[xml::Entry], new: &[xml::Entry]) -> Vec<String>
+{
+    let old_by_path: HashMap<&str, &xml::Entry> =
+        old.iter().map(|e| (e.path.as_str(), e)).collect();
+    let new_by_path: HashMap<&str, &xml::Entry> =
+        new.iter().map(|e| (e.path.as_str(), e)).collect();
+    let mut lines = Vec::new();
+    for entry in new
+    {
+        match old_by_path.get(entry.path.as_str())
+        {
+            None => lines.push(format!("added   {}", entry.path)),
+            Some(o) if !FieldDiff::new(&o.data, &entry.data).is_empty() =>
+                lines.push(format!("changed {}", entry.path)),
+            Some(_) => {},
+        }
+    }
+    for entry in old
+    {
+        if !new_by_path.contains_key(entry.path.as_str())
+        {
+            lines.push(format!("removed {}", entry.path));
+        }
+    }
+    lines
+}
+
+/// Print the changes since the previous export to stderr, and append
+/// them to `export_diff_file` if configured.
+fn reportExportDiff(lines: &[String], conf: &Config) -> Result<(), Error>
+{
+    if lines.is_empty()
+    {
+        eprintln!("No changes since the last export.");
+    }
+    for line in lines
+    {
+        eprintln!("{}", line);
+    }
+    if let Some(file) = &conf.export_diff_file
+    {
+        let mut f = std::fs::OpenOptions::new().create(true).append(true)
+            .open(file).map_err(|e| rterr!("Failed to open {}: {}", file, e))?;
+        let mut content = format!("# {}\n", Utc::now().to_rfc3339());
+        for line in lines
+        {
+            content.push_str(line);
+            content.push('\n');
+        }
+        f.write_all(content.as_bytes()).map_err(
+            |e| rterr!("Failed to write {}: {}", file, e))?;
+    }
+    Ok(())
+}
+
+/// Soft-delete some versions of the entry at `path`, after checking
+/// that they exist and confirming with the user, unless `yes` is
+/// true.
+pub async fn deleteVersions(client: &Client<'_>, path: &str, versions: &[u64],
+                            yes: bool, conf: &Config) -> Result<(), Error>
+{
+    let meta = client.metadata(path).await?;
+    for v in versions
+    {
+        if !meta.versions.contains_key(&v.to_string())
+        {
+            return Err(error!(NotFoundError, "Version {} of {} does not exist",
+                              v, path));
+        }
+    }
+
+    let version_strs: Vec<String> = versions.iter().map(|v| v.to_string())
+        .collect();
+    if !yes && !confirm(&format!("Delete version {} of {}?",
+                                 version_strs.join(", "), path))?
+    {
+        info!(conf, "Cancelled.");
+        return Ok(());
+    }
+    client.deleteVersions(path, versions).await?;
+    info!(conf, "Deleted.");
+    Ok(())
+}
+
+/// Soft-delete the current version of the entry at `path` after
+/// confirmation, unless `yes` is true. Unlike `Client::delete`, the
+/// older versions are kept, and the deleted one can be undeleted.
+pub async fn deleteLatest(client: &Client<'_>, path: &str, yes: bool,
+                          conf: &Config) -> Result<(), Error>
+{
+    let meta = client.metadata(path).await?;
+    let version = meta.current_version;
+    if !yes && !confirm(&format!("Delete the latest version {} of {}? Older \
+                                  versions are kept.", version, path))?
+    {
+        info!(conf, "Cancelled.");
+        return Ok(());
+    }
+    client.deleteLatest(path).await?;
+    info!(conf, "Deleted version {}. Recover it with --undelete {} \
+                 --entry-version {}", version, path, version);
+    Ok(())
+}
+
+pub async fn undeleteVersions(client: &Client<'_>, path: &str,
+                              versions: &[u64], conf: &Config) ->
+    Result<(), Error>
+{
+    let meta = client.metadata(path).await?;
+    for v in versions
+    {
+        if !meta.versions.contains_key(&v.to_string())
+        {
+            return Err(error!(NotFoundError, "Version {} of {} does not exist",
+                              v, path));
+        }
+    }
+    client.undeleteVersions(path, versions).await?;
+    info!(conf, "Undeleted.");
+    Ok(())
+}
+
+/// Retrieve the entry at `path` for exporting. `previous` is the entry
+/// in the previous export, which is reused if deduplicating and it
+/// has the current version.
+async fn exportEntry(client: &Client<'_>, path: &str, opts: &ExportOptions,
+                     previous: Option<&xml::Entry>) ->
+    Result<xml::Entry, Error>
+{
+    let meta = if opts.with_metadata
+    {
+        Some(client.metadata(path).await?)
+    }
+    else if opts.dedupe
+    {
+        // Without metadata the entry is simply retrieved.
+        client.metadata(path).await.ok()
+    }
+    else
+    {
+        None
+    };
+    let version = meta.as_ref().map(|m| m.current_version.to_string());
+    if let (Some(prev), Some(v)) = (previous, &version)
+    {
+        if opts.dedupe && prev.version.as_ref() == Some(v)
+        {
+            return Ok(prev.clone());
+        }
+    }
+    let data = client.get(path).await?;
+    Ok(xml::Entry {
+        path: path.to_owned(),
+        version,
+        created_time: meta.as_ref().and_then(|m| m.current())
+            .map(|v| v.created_time.clone()),
+        data,
+    })
+}
+
+/// Retrieve all the entries for exporting. `old` is the previous
+/// export, for deduplication.
+async fn collectEntries(client: &Client<'_>, opts: &ExportOptions,
+                        old: &[xml::Entry]) -> Result<Vec<xml::Entry>, Error>
+{
+    let old_by_path: HashMap<&str, &xml::Entry> =
+        old.iter().map(|e| (e.path.as_str(), e)).collect();
+    let mut paths: Vec<String> = Vec::new();
+    client.walk(&Path::new(), |path, item| if let KeyOrDir::Key(_) = item
+    {
+        paths.push(path.to_string());
+    }).await?;
+    // The entries are fetched concurrently, up to the concurrency
+    // limit of the client, but kept in path order, so that exports of
+    // the same data are identical.
+    paths.sort();
+    futures_util::future::join_all(
+        paths.iter().map(|p| exportEntry(client, p, opts,
+                                         old_by_path.get(p.as_str()).copied())))
+        .await
+        .into_iter().collect()
+}
+
+/// Runtime info key of what the last export with deduplication
+/// retrieved from Vault, as a JSON object with the digest of the XML
+/// in `export`, and the version of each path retrieved in
+/// `versions`.
+const EXPORT_VERSIONS_KEY: &str = "export_versions";
+
+fn digestHex(data: &[u8]) -> String
+{
+    ring::digest::digest(&ring::digest::SHA256, data).as_ref().iter()
+        .map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The entries of the previous export `old`, parsed from `old_xml`,
+/// that deduplication may reuse. This is only the case if `old_xml`
+/// is what the last export wrote, and the entry has the version that
+/// export retrieved. So an edited file, the file of another profile,
+/// or an entry only kept by a merge is retrieved again.
+fn reusableEntries(old_xml: &[u8], old: &[xml::Entry], conf: &Config) ->
+    Vec<xml::Entry>
+{
+    let recorded: serde_json::Value =
+        getRuntimeInfo(EXPORT_VERSIONS_KEY, conf).ok().flatten()
+        .and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default();
+    if recorded["export"].as_str() != Some(digestHex(old_xml).as_str())
+    {
+        return Vec::new();
+    }
+    old.iter().filter(|e| e.version.is_some() &&
+                      recorded["versions"][&e.path].as_str() ==
+                      e.version.as_deref())
+        .cloned().collect()
+}
+
+/// Record that `xml` was exported, with the versions in `retrieved`,
+/// for `reusableEntries`.
+fn recordExportVersions(xml: &[u8], retrieved: &[xml::Entry],
+                        conf: &Config) -> Result<(), Error>
+{
+    let versions: serde_json::Map<String, serde_json::Value> =
+        retrieved.iter().filter_map(|e| e.version.as_ref().map(
+            |v| (e.path.clone(), serde_json::Value::String(v.clone()))))
+        .collect();
+    let record = serde_json::json!({"export": digestHex(xml),
+                                    "versions": versions});
+    setRuntimeInfo(EXPORT_VERSIONS_KEY, Some(&record.to_string()), conf)
+}
+
+/// Export passwords as an XML string. If `opts.merge` is true, the
+/// entries are merged into the existing local XML. Return the XML
+/// and the number of entries in it.
+async fn passwordsToXML(client: &Client<'_>, conf: &Config,
+                        opts: &ExportOptions) -> Result<(Vec<u8>, usize), Error>
+{
+    let (old_xml, old) = match &conf.local_xml
+    {
+        Some(file) if (opts.merge || opts.diff || opts.dedupe)
+            && std::path::Path::new(file).exists() =>
+        {
+            let old_xml = gpgDecrypt(file)?;
+            let old = xml::parse(&old_xml)?;
+            (old_xml, old)
+        },
+        _ => (Vec::new(), Vec::new()),
+    };
+    let reusable = if opts.dedupe
+    {
+        reusableEntries(&old_xml, &old, conf)
+    }
+    else
+    {
+        Vec::new()
+    };
+    let mut entries = collectEntries(client, opts, &reusable).await?;
+    if opts.diff
+    {
+        reportExportDiff(&diffExports(&old, &entries), conf)?;
+    }
+    // Only what was retrieved is recorded, not what the merge kept.
+    let retrieved = if opts.dedupe { entries.clone() } else { Vec::new() };
+    if opts.merge
+    {
+        entries = xml::merge(old, entries);
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    let xml = xml::write(&entries)?;
+    if opts.dedupe
+    {
+        recordExportVersions(&xml, &retrieved, conf)?;
+    }
+    Ok((xml, entries.len()))
+}
+
+/// Encrypt bytes with GPG, writing the armored result to `target`.
+fn gpgEncrypt(data: Vec<u8>, target: &mut dyn Write, user: &str) ->
+    Result<(), Error>
+{
+    let mut proc = Command::new("gpg").args(
+        ["--yes", "-r", user , "--encrypt", "-a", "-o", "-", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn().map_err(|e| rterr!("Failed to run GPG: {}", e))?;
+
+    // Feed stdin from another thread, otherwise GPG could block on a
+    // full stdout pipe while we are still writing.
+    let mut proc_stdin = proc.stdin.take().unwrap();
+    let feeder = std::thread::spawn(move || proc_stdin.write_all(&data));
+    let mut proc_stdout = proc.stdout.take().unwrap();
+    std::io::copy(&mut proc_stdout, target).map_err(
+        |e| rterr!("Failed to write GPG output: {}", e))?;
+    feeder.join().map_err(|_| rterr!("Failed to input to GPG"))?
+        .map_err(|e| rterr!("Failed to input to GPG: {}", e))?;
+
+    let status = proc.wait().map_err(|_| rterr!("GPG failed to run"))?;
+    if status.success()
+    {
+        Ok(())
+    }
+    else
+    {
+        let code_str = if let Some(code) = status.code()
+        {
+            code.to_string()
+        }
+        else
+        {
+            String::from("??")
+        };
+        Err(rterr!("GPG failed with code: {}", code_str))
+    }
+}
+
+/// Decrypt a file with GPG.
+fn gpgDecrypt(filename: &str) -> Result<Vec<u8>, Error>
+{
+    let output = Command::new("gpg").args(["--quiet", "--decrypt"])
+        .arg(filename)
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .output().map_err(|e| rterr!("Failed to run GPG: {}", e))?;
+    if output.status.success()
+    {
+        Ok(output.stdout)
+    }
+    else
+    {
+        let code_str = if let Some(code) = output.status.code()
+        {
+            code.to_string()
+        }
+        else
+        {
+            String::from("??")
+        };
+        Err(rterr!("GPG failed to decrypt with code: {}", code_str))
+    }
+}
+
+fn gpgUser(conf: &Config) -> Result<&str, Error>
+{
+    conf.gpg_user.as_deref().ok_or_else(|| rterr!("No GPG user provided."))
+}
+
+/// Export all the passwords to `target`. The XML is encrypted for the
+/// configured GPG user, unless `opts.plaintext` is true. Return the
+/// number of entries exported.
+pub async fn writeExport(client: &Client<'_>, conf: &Config,
+                         target: &mut dyn Write, opts: &ExportOptions) ->
+    Result<usize, Error>
+{
+    if opts.plaintext
+    {
+        let (xml, count) = passwordsToXML(client, conf, opts).await?;
+        target.write_all(&xml).map_err(
+            |e| rterr!("Failed to write XML: {}", e))?;
+        Ok(count)
+    }
+    else
+    {
+        let gpg_user = gpgUser(conf)?;
+        let (xml, count) = passwordsToXML(client, conf, opts).await?;
+        gpgEncrypt(xml, target, gpg_user)?;
+        Ok(count)
+    }
+}
+
+/// Check that the encrypted XML export in `file` can be decrypted
+/// and parsed, and that it has `expected` entries.
+fn verifyExport(file: &str, expected: usize, conf: &Config) ->
+    Result<(), Error>
+{
+    let count = xml::parse(&gpgDecrypt(file)?)?.len();
+    if count != expected
+    {
+        return Err(rterr!("Export verification failed: wrote {} entries, \
+                           read back {}", expected, count));
+    }
+    info!(conf, "Export verified with {} entries.", count);
+    Ok(())
+}
+
+/// Export the passwords to the configured local XML file, if the
+/// last export is older than the export period.
+pub async fn exportPasswords(client: &Client<'_>, conf: &Config,
+                             opts: &ExportOptions) -> Result<(), Error>
+{
+    let last_xml_time: DateTime<Utc> = if let Some(t_str) =
+        getRuntimeInfo("last_xml_export_time", conf)?
+    {
+        t_str.parse().unwrap()
+    }
+    else
+    {
+        DateTime::<Utc>::MIN_UTC
+    };
+
+    let now = Utc::now();
+    if (now - last_xml_time).num_seconds() < conf.xml_export_period
+    {
+        return Ok(())
+    }
+
+    info!(conf, "Exporting XML...");
+    let start = std::time::Instant::now();
+    let result = audit::record(conf, "export", None,
+                               exportToLocalXML(client, conf, opts).await);
+    if let Some(file) = &conf.metrics_file
+    {
+        // The export itself is done, so only warn.
+        if let Err(e) = writeMetrics(file, &result, start.elapsed())
+        {
+            eprintln!("Warning: {}", e.message());
+        }
+    }
+    result?;
+    setRuntimeInfo("last_xml_export_time", Some(&now.to_rfc3339()), conf)
+}
+
+/// Export to the local XML file. Return the number of entries.
+async fn exportToLocalXML(client: &Client<'_>, conf: &Config,
+                          opts: &ExportOptions) -> Result<usize, Error>
+{
+    // Encrypt into memory first, so that a failed export does not
+    // clobber the previous file.
+    let mut encrypted: Vec<u8> = Vec::new();
+    let count = writeExport(client, conf, &mut encrypted, opts).await?;
+    let file = conf.local_xml.as_ref().unwrap();
+    std::fs::write(file, encrypted).map_err(
+        |e| rterr!("Failed to write XML export: {}", e))?;
+    if opts.verify || conf.verify_export
+    {
+        verifyExport(file, count, conf)?;
+    }
+    Ok(count)
+}
+
+/// Write metrics about an export to `file` in the text format of the
+/// Prometheus node exporter textfile collector. `result` is the
+/// result of the export with the number of entries. The metrics are
+///
+/// - `vault_hunter_export_last_run_timestamp_seconds`: when the
+///   export finished, as a Unix time.
+/// - `vault_hunter_export_success`: 1 if the export succeeded, 0 if
+///   not.
+/// - `vault_hunter_export_entries`: number of entries exported, 0 if
+///   the export failed.
+/// - `vault_hunter_export_duration_seconds`: how long the export took.
+///
+/// The file is replaced atomically, so the collector never reads a
+/// partial file.
+pub fn writeMetrics(file: &str, result: &Result<usize, Error>,
+                    duration: std::time::Duration) -> Result<(), Error>
+{
+    let metrics = [
+        ("last_run_timestamp_seconds", "gauge",
+         "Unix time when the last export finished.",
+         Utc::now().timestamp().to_string()),
+        ("success", "gauge", "Whether the last export succeeded.",
+         String::from(if result.is_ok() { "1" } else { "0" })),
+        ("entries", "gauge", "Number of entries in the last export.",
+         result.as_ref().map_or(0, |n| *n).to_string()),
+        ("duration_seconds", "gauge", "Duration of the last export.",
+         format!("{:.3}", duration.as_secs_f64())),
+    ];
+    let mut content = String::new();
+    for (name, kind, help, value) in metrics
+    {
+        let name = format!("vault_hunter_export_{}", name);
+        content.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n",
+                                  name, help, name, kind, name, value));
+    }
+    let tmp = format!("{}.tmp", file);
+    std::fs::write(&tmp, content).map_err(
+        |e| rterr!("Failed to write {}: {}", tmp, e))?;
+    std::fs::rename(&tmp, file).map_err(
+        |e| rterr!("Failed to write {}: {}", file, e))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::mock_vault::MockVault;
+
+    #[tokio::test]
+    async fn replaceFieldKeepsOtherFields()
+    {
+        let vault = MockVault::start(|req| match req.method.as_str()
+        {
+            "GET" => (200, serde_json::json!({"data": {
+                "data": {"Password": "x", "Port": 8080, "Tags": ["a", "b"],
+                         "Enabled": true, "Nested": {"k": null}},
+                "metadata": {"version": 4}}}).to_string()),
+            _ => (204, String::new()),
+        }).await;
+        let conf = vault.config();
+        let client = Client::new(&conf).unwrap();
+        replaceField(&client, "a/b", "Note=hi", &conf).await.unwrap();
+
+        let requests = vault.requests();
+        let write = requests.iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(write.path, "/v1/passwords/data/metrowind/a/b");
+        assert_eq!(write.json(), serde_json::json!({
+            "data": {"Password": "x", "Port": 8080, "Tags": ["a", "b"],
+                     "Enabled": true, "Nested": {"k": null}, "Note": "hi"},
+            "options": {"cas": 4}}));
+        // The version comes from the read, not from another request.
+        assert_eq!(requests.len(), 2);
+    }
+
+    #[test]
+    fn totpMatchesRFC6238()
+    {
+        // The SHA-1 vectors in RFC 6238 Appendix B, with the key
+        // "12345678901234567890", truncated to 6 digits.
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        assert_eq!(decodeBase32(secret).unwrap(), b"12345678901234567890");
+        for (time, code) in [(59, "287082"), (1111111109, "081804"),
+                             (1111111111, "050471"), (1234567890, "005924"),
+                             (2000000000, "279037"), (20000000000, "353130")]
+        {
+            let time = DateTime::from_timestamp(time, 0).unwrap();
+            assert_eq!(totpCode(secret, time).unwrap(), code);
+        }
+        let uri = format!("otpauth://totp/x?secret={}&issuer=y", secret);
+        let time = DateTime::from_timestamp(59, 0).unwrap();
+        assert_eq!(totpCode(&uri, time).unwrap(), "287082");
+    }
+
+    #[test]
+    fn totpHonorsURIParameters()
+    {
+        // The 8-digit vectors in RFC 6238 Appendix B. Each algorithm
+        // has a key of its own length.
+        let sha1 = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        let sha256 = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA";
+        let sha512 = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBV\
+                      GY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA";
+        for (time, codes) in [
+            (59, ["94287082", "46119246", "90693936"]),
+            (1111111109, ["07081804", "68084774", "25091201"]),
+            (1234567890, ["89005924", "91819424", "93441116"]),
+            (20000000000, ["65353130", "77737706", "47863826"])]
+        {
+            let time = DateTime::from_timestamp(time, 0).unwrap();
+            for ((secret, algorithm), code) in
+                [(sha1, "SHA1"), (sha256, "SHA256"), (sha512, "SHA512")]
+                .into_iter().zip(codes)
+            {
+                let uri = format!("otpauth://totp/x?secret={}&algorithm={}\
+                                   &digits=8", secret, algorithm);
+                assert_eq!(totpCode(&uri, time).unwrap(), code);
+            }
+        }
+
+        // With a period of 60 seconds, 118 is in the same step as 59
+        // is with 30.
+        let uri = format!("otpauth://totp/x?secret={}&period=60", sha1);
+        let time = DateTime::from_timestamp(118, 0).unwrap();
+        assert_eq!(totpCode(&uri, time).unwrap(), "287082");
+
+        for bad in ["algorithm=MD5", "digits=10", "period=0"]
+        {
+            let uri = format!("otpauth://totp/x?secret={}&{}", sha1, bad);
+            assert!(totpCode(&uri, time).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn totpFieldIsSecret()
+    {
+        let conf = Config::default();
+        assert!(isSecret("Password", &conf));
+        assert!(isSecret(&conf.totp_field, &conf));
+        assert!(!isSecret("Username", &conf));
+    }
+
+    #[tokio::test]
+    async fn writesWorkWithKVv1()
+    {
+        let vault = MockVault::start(|req| match (req.method.as_str(),
+                                                  req.path.as_str())
+        {
+            ("GET", "/v1/passwords/metrowind/a") => (200, serde_json::json!(
+                {"data": {"Password": "x", "Port": 8080}}).to_string()),
+            ("GET", _) => (404, serde_json::json!({"errors": []}).to_string()),
+            _ => (204, String::new()),
+        }).await;
+        let mut conf = vault.config();
+        conf.kv_version = 1;
+        let client = Client::new(&conf).unwrap();
+        replaceField(&client, "a", "Note=hi", &conf).await.unwrap();
+        touch(&client, "a", true, &conf).await.unwrap();
+        rotate(&client, "a", true, &conf).await.unwrap();
+        copyEntry(&client, "a", "b", false, &conf).await.unwrap();
+
+        let requests = vault.requests();
+        let writes: Vec<_> = requests.iter().filter(|r| r.method == "POST")
+            .collect();
+        assert_eq!(writes.len(), 4);
+        for write in writes
+        {
+            // The entry itself is the body, with no check-and-set.
+            assert_eq!(write.json()["Port"], 8080);
+            assert!(write.json().get("options").is_none());
+        }
+        assert!(requests.iter().all(|r| !r.path.contains("metadata")));
+    }
+
+    #[tokio::test]
+    async fn deleteLatestDeletesTheDataPath()
+    {
+        let vault = MockVault::start(|req| match req.method.as_str()
+        {
+            "GET" => (200, serde_json::json!({"data": {
+                "current_version": 3, "created_time": "", "updated_time": ""}})
+                      .to_string()),
+            _ => (204, String::new()),
+        }).await;
+        let conf = vault.config();
+        let client = Client::new(&conf).unwrap();
+        deleteLatest(&client, "a/b", true, &conf).await.unwrap();
+
+        let requests = vault.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, "DELETE");
+        assert_eq!(requests[1].path, "/v1/passwords/data/metrowind/a/b");
+    }
+
+    #[tokio::test]
+    async fn pruneRefusesReadOnlyBeforeSearching()
+    {
+        let vault = MockVault::start(|_| (204, String::new())).await;
+        let mut conf = vault.config();
+        conf.read_only = true;
+        let client = Client::new(&conf).unwrap();
+        // Without yes, this would prompt if it got that far.
+        let e = prune(&client, &Matcher::substring(""), false, &conf).await
+            .unwrap_err();
+        assert!(e.message().contains("read-only mode"), "{}", e.message());
+        assert!(vault.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn queryWorksInReadOnlyMode()
+    {
+        let vault = MockVault::start(treeHandler).await;
+        let mut conf = vault.config();
+        conf.read_only = true;
+        let client = Client::new(&conf).unwrap();
+        query(&client, &Matcher::substring("b"), false, &conf).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleteVersionsPostsTheVersions()
+    {
+        let vault = MockVault::start(|req| match req.method.as_str()
+        {
+            "GET" => (200, serde_json::json!({"data": {
+                "current_version": 3, "created_time": "", "updated_time": "",
+                "versions": {"1": {"created_time": ""},
+                             "3": {"created_time": ""}}}}).to_string()),
+            _ => (204, String::new()),
+        }).await;
+        let conf = vault.config();
+        let client = Client::new(&conf).unwrap();
+        deleteVersions(&client, "a/b", &[1, 3], true, &conf).await.unwrap();
+
+        let requests = vault.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, "POST");
+        assert_eq!(requests[1].path, "/v1/passwords/delete/metrowind/a/b");
+        assert_eq!(requests[1].json(), serde_json::json!({"versions": [1, 3]}));
+    }
+
+    #[tokio::test]
+    async fn copyAuditsReadAndWriteSeparately()
+    {
+        // Both entries exist, so the copy is refused without --force.
+        let vault = MockVault::start(|_| (200, serde_json::json!({"data": {
+            "data": {"Password": "x"}, "current_version": 1,
+            "created_time": "", "updated_time": ""}}).to_string())).await;
+        let mut conf = vault.config();
+        let log = format!("{}.audit", vault.cache_path);
+        conf.audit_log = Some(log.clone());
+        let client = Client::new(&conf).unwrap();
+        assert!(copyEntry(&client, "a", "b", false, &conf).await.is_err());
+
+        let content = std::fs::read_to_string(&log).unwrap();
+        std::fs::remove_file(&log).unwrap();
+        let lines: Vec<serde_json::Value> = content.lines()
+            .map(|l| serde_json::from_str(l).unwrap()).collect();
+        let outcomes: Vec<_> = lines.iter()
+            .map(|l| (l["operation"].clone(), l["success"].clone())).collect();
+        assert_eq!(outcomes, [(serde_json::json!("copy-read"), true.into()),
+                              (serde_json::json!("copy-write"), false.into())]);
+    }
+
+    /// A tree of `c`, `a`, and `d/b`, listed out of order, where each
+    /// entry has its path as the password.
+    fn treeHandler(req: &crate::mock_vault::Request) -> (u16, String)
+    {
+        let json = match req.path.trim_end_matches('/')
+        {
+            "/v1/passwords/metadata/metrowind" =>
+                serde_json::json!({"data": {"keys": ["c", "d/", "a"]}}),
+            "/v1/passwords/metadata/metrowind/d" =>
+                serde_json::json!({"data": {"keys": ["b"]}}),
+            p => serde_json::json!({"data": {"data": {
+                "Password": p.trim_start_matches(
+                    "/v1/passwords/data/metrowind/")}}}),
+        };
+        (200, json.to_string())
+    }
+
+    #[tokio::test]
+    async fn exportIsBoundedAndOrdered()
+    {
+        let vault = MockVault::withDelay(
+            treeHandler, std::time::Duration::from_millis(50)).await;
+        let mut conf = vault.config();
+        conf.concurrency = 2;
+        let client = Client::new(&conf).unwrap();
+        let entries = collectEntries(&client, &ExportOptions::default(), &[])
+            .await.unwrap();
+
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a", "c", "d/b"]);
+        for entry in &entries
+        {
+            assert_eq!(entry.data["Password"], entry.path);
+        }
+        // Listing is sequential, and the three gets share two slots.
+        assert_eq!(vault.maxInFlight(), 2);
+    }
+
+    #[test]
+    fn readLineReturnsNoneAtEndOfInput()
+    {
+        let mut input = std::io::Cursor::new("a\r\n\nlast");
+        assert_eq!(readLine(&mut input).unwrap().as_deref(), Some("a"));
+        assert_eq!(readLine(&mut input).unwrap().as_deref(), Some(""));
+        assert_eq!(readLine(&mut input).unwrap().as_deref(), Some("last"));
+        assert_eq!(readLine(&mut input).unwrap(), None);
+        assert_eq!(readLine(&mut std::io::empty()).unwrap(), None);
+    }
+
+    /// A Vault with only the entry `a`, at `version`.
+    fn versionedHandler(version: u64) ->
+        impl Fn(&crate::mock_vault::Request) -> (u16, String)
+    {
+        move |req| {
+            let json = match (req.method.as_str(), req.path.as_str())
+            {
+                ("LIST", _) => serde_json::json!({"data": {"keys": ["a"]}}),
+                ("GET", "/v1/passwords/metadata/metrowind/a") =>
+                    serde_json::json!({"data": {
+                        "current_version": version, "created_time": "",
+                        "updated_time": ""}}),
+                _ => serde_json::json!({"data": {"data": {"Password": "new"}}}),
+            };
+            (200, json.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn dedupeOnlyReusesWhatTheLastExportRetrieved()
+    {
+        let vault = MockVault::start(versionedHandler(2)).await;
+        let conf = vault.config();
+        let client = Client::new(&conf).unwrap();
+        let opts = ExportOptions { dedupe: true, ..Default::default() };
+        let old = vec![xml::Entry {
+            path: String::from("a"), version: Some(String::from("2")),
+            created_time: None,
+            data: StringMap::from([(String::from("Password"),
+                                    String::from("old"))]),
+        }];
+        let old_xml = xml::write(&old).unwrap();
+        let password = |entries: &[xml::Entry]| entries[0].data["Password"]
+            .clone();
+
+        // Nothing recorded yet.
+        assert!(reusableEntries(&old_xml, &old, &conf).is_empty());
+
+        recordExportVersions(&old_xml, &old, &conf).unwrap();
+        let reusable = reusableEntries(&old_xml, &old, &conf);
+        assert_eq!(reusable.len(), 1);
+        let entries = collectEntries(&client, &opts, &reusable).await.unwrap();
+        assert_eq!(password(&entries), "old");
+        // Another file, even with the same entries in it.
+        assert!(reusableEntries(b"<other/>", &old, &conf).is_empty());
+
+        // The entry was written since.
+        let vault = MockVault::start(versionedHandler(3)).await;
+        let mut newer = vault.config();
+        newer.cache_path = conf.cache_path.clone();
+        let client = Client::new(&newer).unwrap();
+        let reusable = reusableEntries(&old_xml, &old, &newer);
+        let entries = collectEntries(&client, &opts, &reusable).await.unwrap();
+        assert_eq!(password(&entries), "new");
+        assert_eq!(entries[0].version.as_deref(), Some("3"));
+
+        // The file says another version than was retrieved.
+        let mut edited = old.clone();
+        edited[0].version = Some(String::from("1"));
+        recordExportVersions(&old_xml, &edited, &conf).unwrap();
+        assert!(reusableEntries(&old_xml, &old, &conf).is_empty());
+    }
+
+    #[tokio::test]
+    async fn failedMetricsDoNotFailExport()
+    {
+        let vault = MockVault::start(treeHandler).await;
+        let dir = std::env::temp_dir();
+        let xml_file = dir.join(format!("vault-hunter-test-{}.xml",
+                                        std::process::id()));
+        let mut conf = vault.config();
+        conf.local_xml = Some(xml_file.to_string_lossy().into_owned());
+        conf.metrics_file = Some(dir.join("no-such-dir/metrics.prom")
+                                 .to_string_lossy().into_owned());
+        // Logging in would create the runtime info file.
+        setRuntimeInfo("last_xml_export_time", None, &conf).unwrap();
+        let client = Client::new(&conf).unwrap();
+        let opts = ExportOptions { plaintext: true, ..Default::default() };
+        let result = exportPasswords(&client, &conf, &opts).await;
+        let written = std::fs::read(&xml_file);
+        let _ = std::fs::remove_file(&xml_file);
+        result.unwrap();
+        assert!(!written.unwrap().is_empty());
+        assert!(getRuntimeInfo("last_xml_export_time", &conf).unwrap()
+                .is_some());
+    }
+
+    #[tokio::test]
+    async fn exportIsDeterministic()
+    {
+        // The second run lists the root in another order.
+        let runs = std::sync::atomic::AtomicUsize::new(0);
+        let vault = MockVault::start(move |req| {
+            let root = req.path == "/v1/passwords/metadata/metrowind/";
+            if root && runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+                == 1
+            {
+                return (200, serde_json::json!(
+                    {"data": {"keys": ["a", "d/", "c"]}}).to_string());
+            }
+            treeHandler(req)
+        }).await;
+        let conf = vault.config();
+        let client = Client::new(&conf).unwrap();
+        let opts = ExportOptions::default();
+        let first = collectEntries(&client, &opts, &[]).await.unwrap();
+        let second = collectEntries(&client, &opts, &[]).await.unwrap();
+        assert_eq!(xml::write(&first).unwrap(), xml::write(&second).unwrap());
+    }
 }
@@ -1,43 +1,460 @@
 #![allow(non_snake_case)]
 
-use tokio;
-
 #[macro_use]
 mod error;
+#[macro_use]
+mod output;
 mod config;
 mod runtime_info;
+mod trace;
 mod vault_client;
 mod hunter;
+mod xml;
+mod audit;
+mod source;
+mod oidc;
+mod crypt;
+#[cfg(test)]
+mod mock_vault;
 
 use error::Error;
 
-#[tokio::main]
-async fn main() -> Result<(), Error>
+fn cli() -> clap::App<'static, 'static>
 {
-    let matches = clap::App::new("Vault Hunter")
+    clap::App::new("Vault Hunter")
         .version("0.1")
         .author("MetroWind <chris.corsair@gmail.com>")
         .about("Personal password manager on top of HashiCorp Vault.")
+        .after_help(error::EXIT_CODES_HELP)
         .arg(clap::Arg::with_name("PATTERN")
-             .help("Pattern to search for")
+             .help("Pattern to search for. Or a URL like \
+                    vault://user@host[:port]/mount/path to reveal the entry \
+                    at path in that Vault, without the config file. Use \
+                    vault+http:// for plain HTTP")
              .required(false)
              .index(1))
+        .arg(clap::Arg::with_name("type")
+             .long("type").value_name("TYPE").takes_value(true)
+             .possible_values(&["key", "dir", "both"]).default_value("key")
+             .help("Search for keys, directories, or both"))
+        .arg(clap::Arg::with_name("sort")
+             .long("sort").value_name("ORDER").takes_value(true)
+             .possible_values(&["path", "name", "modified"])
+             .default_value("path")
+             .help("Order of search results"))
+        .arg(clap::Arg::with_name("regex")
+             .long("regex")
+             .help("Match PATTERN as a regex on the full path. Named groups \
+                    are shown in the results"))
+        .arg(clap::Arg::with_name("glob")
+             .long("glob").conflicts_with("regex")
+             .help("Match PATTERN as a shell glob on the full path. * does \
+                    not match /, use ** for any number of directories"))
+        .arg(clap::Arg::with_name("delete-cache")
+             .long("delete-cache")
+             .help("Delete the local token cache and export time. This does \
+                    not revoke the token; use --logout for that"))
+        .arg(clap::Arg::with_name("paths")
+             .long("paths")
+             .help("Print the paths of the config file and the runtime info \
+                    file, and whether they exist"))
+        .arg(clap::Arg::with_name("merge-profiles")
+             .long("merge-profiles").conflicts_with("offline")
+             .help("Search this config and all the profiles in it, with \
+                    each result prefixed by its profile name, or \
+                    “default” for this config"))
+        .arg(clap::Arg::with_name("offline")
+             .long("offline")
+             .help("Search and reveal in the local XML export instead of \
+                    Vault. The export may be out of date"))
+        .arg(clap::Arg::with_name("endpoint")
+             .long("endpoint").value_name("URL").takes_value(true)
+             .help("URL of the Vault API. Takes precedence over end_point in \
+                    the config, which takes precedence over VAULT_ADDR"))
+        .arg(clap::Arg::with_name("search-values")
+             .long("search-values")
+             .help("Also match PATTERN against the values of the fields. \
+                    This retrieves every entry"))
         .arg(clap::Arg::with_name("token-info")
              .long("token-info").help("Print token info"))
+        .arg(clap::Arg::with_name("login")
+             .long("login")
+             .help("Login and cache the token, then exit"))
+        .arg(clap::Arg::with_name("askpass")
+             .long("askpass").value_name("PATH").takes_value(true)
+             .help("Read the login password as one line from PATH, e.g. a \
+                    FIFO written by a graphical prompt"))
+        .arg(clap::Arg::with_name("jwt")
+             .long("jwt").value_name("JWT").takes_value(true)
+             .help("JWT to login with, for the jwt and oidc auth methods. \
+                    Other users can see this in the process list; prefer \
+                    VH_JWT or jwt_file"))
+        .arg(clap::Arg::with_name("token")
+             .long("token").value_name("TOKEN").takes_value(true)
+             .help("Vault token to use instead of logging in. Other users \
+                    can see this in the process list; prefer VAULT_TOKEN"))
         .arg(clap::Arg::with_name("logout")
              .long("logout").help("Logout before doing anything"))
+        .arg(clap::Arg::with_name("quiet")
+             .long("quiet").short("q")
+             .help("Do not print informational messages"))
+        .arg(clap::Arg::with_name("trace")
+             .long("trace")
+             .help("Print HTTP requests and responses to stderr, with \
+                    credentials redacted"))
+        .arg(clap::Arg::with_name("prefer-cache")
+             .long("prefer-cache")
+             .help("Use the cached token without checking it first, and \
+                    login again only if Vault refuses it"))
+        .arg(clap::Arg::with_name("error-json")
+             .long("error-json").help("Print errors as JSON"))
+        .arg(clap::Arg::with_name("read-only")
+             .long("read-only").help("Refuse to write anything to Vault"))
+        .arg(clap::Arg::with_name("health")
+             .long("health").help("Print the status of Vault"))
+        .arg(clap::Arg::with_name("health-json")
+             .long("health-json")
+             .help("Print the full health information of Vault as JSON"))
         .arg(clap::Arg::with_name("list-mounts")
              .long("list-mounts").help("List mounts"))
-        .get_matches();
+        .arg(clap::Arg::with_name("copy-json")
+             .long("copy-json")
+             .help("Copy the whole entry to the clipboard as JSON"))
+        .arg(clap::Arg::with_name("fill")
+             .long("fill").conflicts_with("copy-json")
+             .help("Copy the fields of the entry to the clipboard one by one"))
+        .arg(clap::Arg::with_name("copy-username")
+             .long("copy-username").short("u")
+             .conflicts_with_all(&["copy-json", "fill", "copy-all",
+                                   "copy-field"])
+             .help("Copy the username instead of the password. The field is \
+                    username_field in the config, by default “Username”"))
+        .arg(clap::Arg::with_name("copy-all")
+             .long("copy-all").visible_alias("interactive-reveal")
+             .conflicts_with_all(&["copy-json", "fill"])
+             .help("Choose fields of the entry to copy to the clipboard from \
+                    a menu, one after another, until q"))
+        .arg(clap::Arg::with_name("copy-field")
+             .long("copy-field").value_name("FIELD").takes_value(true)
+             .conflicts_with_all(&["copy-json", "fill", "copy-all"])
+             .help("Copy FIELD instead of the password"))
+        .arg(clap::Arg::with_name("raw")
+             .long("raw").conflicts_with_all(&["copy-json", "fill", "copy-all"])
+             .help("Print only the value of the field"))
+        .arg(clap::Arg::with_name("output-file")
+             .long("output-file").value_name("PATH").takes_value(true)
+             .conflicts_with_all(&["copy-json", "fill", "copy-all", "raw"])
+             .help("Write the entry as JSON, or FIELD with --copy-field, to \
+                    PATH"))
+        .arg(clap::Arg::with_name("clear-after")
+             .long("clear-after").value_name("SECS").takes_value(true)
+             .help("Clear the clipboard SECS seconds after copying, 0 for \
+                    never. Overrides clipboard_clear_secs in the config"))
+        .arg(clap::Arg::with_name("format")
+             .long("format").value_name("FORMAT").takes_value(true)
+             .possible_values(&["text", "env"]).default_value("text")
+             .help("How to print the entry. “env” prints shell export \
+                    statements, safely quoted for eval, without the \
+                    password unless --reveal is given"))
+        .arg(clap::Arg::with_name("reveal")
+             .long("reveal")
+             .help("Include secret fields in --format env and --query"))
+        .arg(clap::Arg::with_name("show-all")
+             .long("show-all")
+             .help("Show the values of the fields in redact_fields"))
+        .arg(clap::Arg::with_name("strict")
+             .long("strict")
+             .help("Fail if the entry has unexpected fields"))
+        .arg(clap::Arg::with_name("can")
+             .long("can").value_name("PATH").takes_value(true)
+             .help("Print the capabilities of the token on PATH"))
+        .arg(clap::Arg::with_name("metadata")
+             .long("metadata").value_name("PATH").takes_value(true)
+             .help("Print the versions of the entry at PATH"))
+        .arg(clap::Arg::with_name("delete-version")
+             .long("delete-version").value_name("PATH").takes_value(true)
+             .requires("entry-version")
+             .help("Soft-delete some versions of the entry at PATH"))
+        .arg(clap::Arg::with_name("delete-latest")
+             .long("delete-latest").value_name("PATH").takes_value(true)
+             .help("Soft-delete only the current version of the entry at \
+                    PATH, keeping the older ones"))
+        .arg(clap::Arg::with_name("undelete")
+             .long("undelete").value_name("PATH").takes_value(true)
+             .requires("entry-version")
+             .help("Recover soft-deleted versions of the entry at PATH"))
+        .arg(clap::Arg::with_name("entry-version")
+             .long("entry-version").value_name("N").takes_value(true)
+             .multiple(true).number_of_values(1)
+             .help("Version to operate on. Can be given multiple times"))
+        .arg(clap::Arg::with_name("copy-entry")
+             .long("copy-entry").value_names(&["FROM", "TO"])
+             .number_of_values(2)
+             .help("Copy the entry at FROM to TO"))
+        .arg(clap::Arg::with_name("batch-get")
+             .long("batch-get").value_name("PATH").multiple(true)
+             .min_values(0)
+             .help("Print the entries at the PATHs as a JSON object keyed \
+                    by path. Read the paths from stdin, one per line, if \
+                    none is given. Secret fields are left out unless \
+                    --reveal is given"))
+        .arg(clap::Arg::with_name("keys")
+             .long("keys").value_name("PATH").takes_value(true)
+             .help("List the field names of the entry at PATH without \
+                    retrieving the values"))
+        .arg(clap::Arg::with_name("replace-field")
+             .long("replace-field").value_names(&["PATH", "KEY=VALUE"])
+             .number_of_values(2)
+             .help("Set one field of the entry at PATH, keeping the other \
+                    fields. A VALUE of - is read from stdin, so that it is \
+                    not on the command line"))
+        .arg(clap::Arg::with_name("export-template")
+             .long("export-template").value_name("TEMPLATE")
+             .takes_value(true)
+             .help("Print every entry, or those matching PATTERN, through \
+                    TEMPLATE, like \"{path}: {Password}\\n\". {{ and }} are \
+                    literal braces"))
+        .arg(clap::Arg::with_name("template-strict")
+             .long("template-strict").requires("export-template")
+             .help("Fail on a field missing from an entry, instead of \
+                    rendering it empty"))
+        .arg(clap::Arg::with_name("has-field")
+             .long("has-field").value_names(&["PATH", "KEY"])
+             .number_of_values(2)
+             .help("Print nothing, and exit with 0 if the entry at PATH has \
+                    the field KEY, 3 if there is no entry, or 7 if the entry \
+                    does not have the field"))
+        .arg(clap::Arg::with_name("pretty-json")
+             .long("pretty-json").value_names(&["PATH", "FIELD"])
+             .min_values(1).max_values(2)
+             .help("Pretty-print the JSON document in FIELD of the entry at \
+                    PATH. FIELD defaults to json_field in the config, which \
+                    defaults to Password"))
+        .arg(clap::Arg::with_name("touch")
+             .long("touch").value_name("PATH").takes_value(true)
+             .help("Write the entry at PATH back unchanged as a new version"))
+        .arg(clap::Arg::with_name("rotate")
+             .long("rotate").value_name("PATH").takes_value(true)
+             .help("Replace the password of the entry at PATH with a \
+                    generated one, and copy it"))
+        .arg(clap::Arg::with_name("compare")
+             .long("compare").value_names(&["A", "B"])
+             .number_of_values(2)
+             .help("Compare the entries at A and B field by field"))
+        .arg(clap::Arg::with_name("prune")
+             .long("prune").value_name("PATTERN").takes_value(true)
+             .help("Delete all entries matching PATTERN"))
+        .arg(clap::Arg::with_name("query")
+             .long("query").value_name("PATTERN").takes_value(true)
+             .requires("json")
+             .help("Search for PATTERN without prompting. Print the entry if \
+                    exactly one matches, or the matching paths with exit \
+                    code 6"))
+        .arg(clap::Arg::with_name("stats")
+             .long("stats").help("Print a summary of the vault"))
+        .arg(clap::Arg::with_name("detailed")
+             .long("detailed").requires("stats")
+             .help("Also count the fields of every entry in --stats"))
+        .arg(clap::Arg::with_name("json")
+             .long("json").help("Print the output as JSON"))
+        .arg(clap::Arg::with_name("expiring")
+             .long("expiring").value_name("DAYS").takes_value(true)
+             .min_values(0).max_values(1)
+             .help("List entries whose __expires date is within DAYS, by \
+                    default rotation_warn_days, or already past"))
+        .arg(clap::Arg::with_name("show-missing")
+             .long("show-missing").requires("expiring")
+             .help("Also list entries without a valid __expires"))
+        .arg(clap::Arg::with_name("self-test")
+             .long("self-test").hidden(true)
+             .help("Write, read back, and delete a scratch entry"))
+        .arg(clap::Arg::with_name("import")
+             .long("import").value_name("FILE").takes_value(true)
+             .help("Import entries from an XML export, encrypted or not"))
+        .arg(clap::Arg::with_name("dry-run")
+             .long("dry-run").requires("import")
+             .help("Only show what the import would change"))
+        .arg(clap::Arg::with_name("yes")
+             .long("yes").short("y").help("Do not ask for confirmation"))
+        .arg(clap::Arg::with_name("force")
+             .long("force").help("Overwrite existing entries or files"))
+        .arg(clap::Arg::with_name("export-to-stdout")
+             .long("export-to-stdout")
+             .help("Export all passwords as GPG-encrypted XML to stdout"))
+        .arg(clap::Arg::with_name("plaintext")
+             .long("plaintext").requires("export-to-stdout")
+             .help("Do not encrypt the XML export"))
+        .arg(clap::Arg::with_name("with-metadata")
+             .long("with-metadata")
+             .help("Include entry versions and times in the XML export"))
+        .arg(clap::Arg::with_name("merge-export")
+             .long("merge-export")
+             .help("Merge the XML export into the existing local XML"))
+        .arg(clap::Arg::with_name("diff-export")
+             .long("diff-export")
+             .help("Report the paths added, removed, or changed since the \
+                    last local XML export"))
+        .arg(clap::Arg::with_name("dedupe-export")
+             .long("dedupe-export")
+             .help("Reuse the entries of the last local XML export that \
+                    have not changed since, instead of retrieving them"))
+        .arg(clap::Arg::with_name("metrics-file")
+             .long("metrics-file").value_name("PATH").takes_value(true)
+             .help("Write Prometheus metrics about the export to PATH"))
+        .arg(clap::Arg::with_name("no-export")
+             .long("no-export")
+             .help("Do not export the local XML on this run"))
+        .arg(clap::Arg::with_name("verify-export")
+             .long("verify-export")
+             .help("Check that the local XML can be read back after export"))
+}
+
+#[tokio::main]
+async fn main()
+{
+    let matches = cli().get_matches();
+
+    let code = match run(&matches).await
+    {
+        Ok(()) => error::EXIT_SUCCESS,
+        Err(e) =>
+        {
+            if matches.is_present("error-json")
+            {
+                eprintln!("{}", serde_json::json!({"error": {
+                    "type": e.kind(), "message": e.message()}}));
+            }
+            else
+            {
+                eprintln!("{}", e);
+            }
+            error::exitCodeFor(&e)
+        },
+    };
+    std::process::exit(code);
+}
+
+/// Build the matcher for `pattern` according to the search mode in
+/// the arguments.
+fn makeMatcher(matches: &clap::ArgMatches<'_>, pattern: &str) ->
+    Result<vault_client::Matcher, Error>
+{
+    if matches.is_present("regex")
+    {
+        vault_client::Matcher::regex(pattern)
+    }
+    else if matches.is_present("glob")
+    {
+        vault_client::Matcher::glob(pattern)
+    }
+    else
+    {
+        Ok(vault_client::Matcher::substring(pattern))
+    }
+}
+
+/// The versions given with `--entry-version`.
+fn entryVersions(matches: &clap::ArgMatches<'_>) -> Result<Vec<u64>, Error>
+{
+    matches.values_of("entry-version").unwrap_or_default()
+        .map(|v| v.parse::<u64>().map_err(
+            |_| rterr!("Invalid version: {}", v)))
+        .collect()
+}
+
+/// Create a client for the profile `name` and log in. Report the
+/// error and return None if that fails, so that the other profiles
+/// can still be searched.
+async fn loginProfile<'a>(name: &str, conf: &'a config::Config) ->
+    Option<Box<dyn source::SecretSource + 'a>>
+{
+    let result = async {
+        let mut client = vault_client::Client::new(conf)?;
+        client.login().await?;
+        Ok::<_, Error>(client)
+    }.await;
+    match result
+    {
+        Ok(client) => Some(Box::new(client)),
+        Err(e) =>
+        {
+            eprintln!("{}: {}", name, e);
+            None
+        },
+    }
+}
 
-    let conf = if let Some(path) = config::findConfigFile()
+async fn run(matches: &clap::ArgMatches<'_>) -> Result<(), Error>
+{
+    let url = matches.value_of("PATTERN").filter(|p| config::isVaultURL(p));
+    let mut conf = if let Some(url) = url
+    {
+        config::parseVaultURL(url)?.0
+    }
+    else if let Some(path) = config::findConfigFile()
     {
         config::Config::fromfile(&path)?
     }
     else
     {
-        config::Config::default()
+        let mut conf = config::Config::default();
+        if let Some(addr) = config::vaultAddr()
+        {
+            conf.end_point = addr;
+        }
+        conf
     };
+    if let Some(url) = matches.value_of("endpoint")
+    {
+        conf.end_point = config::normalizeEndPoint(url);
+    }
+    if matches.is_present("read-only")
+    {
+        conf.read_only = true;
+    }
+    if matches.is_present("quiet")
+    {
+        conf.quiet = true;
+    }
+    if let Some(secs) = matches.value_of("clear-after")
+    {
+        conf.clipboard_clear_secs = secs.parse().map_err(
+            |_| rterr!("Invalid number of seconds: {}", secs))?;
+    }
+    if let Some(path) = matches.value_of("askpass")
+    {
+        conf.askpass = Some(path.to_owned());
+    }
+    if let Some(jwt) = matches.value_of("jwt")
+    {
+        conf.jwt = Some(jwt.to_owned());
+    }
+    if let Some(token) = matches.value_of("token")
+    {
+        conf.token = Some(token.to_owned());
+    }
+    if let Some(file) = matches.value_of("metrics-file")
+    {
+        conf.metrics_file = Some(file.to_owned());
+    }
+    if matches.is_present("trace")
+    {
+        conf.trace = true;
+    }
+    if matches.is_present("prefer-cache")
+    {
+        conf.prefer_cache = true;
+    }
+
+    if matches.is_present("paths")
+    {
+        hunter::printPaths(&conf);
+        return Ok(());
+    }
+    if matches.is_present("delete-cache")
+    {
+        return hunter::deleteCache(&conf, matches.is_present("yes"));
+    }
 
     if matches.is_present("logout")
     {
@@ -47,14 +464,32 @@ async fn main() -> Result<(), Error>
             client.logout().await?;
         }
     }
-    if matches.is_present("token-info")
+    if matches.is_present("login")
     {
         let mut client = vault_client::Client::new(&conf)?;
+        return hunter::loginOnly(&mut client).await;
+    }
+    if matches.is_present("token-info")
+    {
+        let client = vault_client::Client::new(&conf)?;
         client.loginUsingCachedToken()?;
         let info: serde_json::Value = client.lookupToken().await?;
         println!("{}", serde_json::to_string_pretty(&info).unwrap());
         return Ok(());
     }
+    if matches.is_present("health")
+    {
+        let client = vault_client::Client::new(&conf)?;
+        println!("{}", client.health().await?);
+        return Ok(());
+    }
+    if matches.is_present("health-json")
+    {
+        let client = vault_client::Client::new(&conf)?;
+        let info = client.healthDetails().await?;
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+        return Ok(());
+    }
     if matches.is_present("list-mounts")
     {
         let mut client = vault_client::Client::new(&conf)?;
@@ -64,18 +499,342 @@ async fn main() -> Result<(), Error>
         return Ok(());
     }
 
+    if let Some(path) = matches.value_of("can")
+    {
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        println!("{}", client.capabilities(path).await?.join(", "));
+        return Ok(());
+    }
+
+    if let Some(path) = matches.value_of("metadata")
+    {
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return audit::record(&conf, "metadata", Some(path),
+                             hunter::printMetadata(&client, path).await);
+    }
+    if let Some(path) = matches.value_of("keys")
+    {
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return audit::record(&conf, "keys", Some(path),
+                             hunter::printKeys(&client, path).await);
+    }
+    if let Some(path) = matches.value_of("delete-version")
+    {
+        let versions = entryVersions(matches)?;
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = hunter::deleteVersions(&client, path, &versions,
+                                            matches.is_present("yes"), &conf)
+            .await;
+        return audit::record(&conf, "delete-version", Some(path), result);
+    }
+    if let Some(path) = matches.value_of("delete-latest")
+    {
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = hunter::deleteLatest(&client, path,
+                                          matches.is_present("yes"), &conf)
+            .await;
+        return audit::record(&conf, "delete-latest", Some(path), result);
+    }
+    if let Some(path) = matches.value_of("undelete")
+    {
+        let versions = entryVersions(matches)?;
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = hunter::undeleteVersions(&client, path, &versions,
+                                              &conf).await;
+        return audit::record(&conf, "undelete", Some(path), result);
+    }
+
+    if let Some(mut paths) = matches.values_of("copy-entry")
+    {
+        let from = paths.next().unwrap();
+        let to = paths.next().unwrap();
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return hunter::copyEntry(&client, from, to,
+                                 matches.is_present("force"), &conf).await;
+    }
+
+    if let Some(mut paths) = matches.values_of("compare")
+    {
+        let a = paths.next().unwrap();
+        let b = paths.next().unwrap();
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = hunter::compareEntries(&client, a, b, &conf).await;
+        let result = audit::record(&conf, "compare", Some(a), result);
+        return audit::record(&conf, "compare", Some(b), result);
+    }
+
+    if matches.is_present("batch-get")
+    {
+        let paths: Vec<String> = if let Some(ps) =
+            matches.values_of("batch-get")
+        {
+            ps.map(|p| p.to_owned()).collect()
+        }
+        else
+        {
+            let input = std::io::read_to_string(std::io::stdin()).map_err(
+                |e| rterr!("Failed to read paths: {}", e))?;
+            input.lines().map(|l| l.trim()).filter(|l| !l.is_empty())
+                .map(|l| l.to_owned()).collect()
+        };
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return hunter::batchGet(&client, &paths, matches.is_present("reveal"),
+                                &conf).await;
+    }
+
+    if let Some(path) = matches.value_of("rotate")
+    {
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = hunter::rotate(&client, path, matches.is_present("yes"),
+                                    &conf).await;
+        return audit::record(&conf, "rotate", Some(path), result);
+    }
+
+    if let Some(mut args) = matches.values_of("pretty-json")
+    {
+        let path = args.next().unwrap();
+        let field = args.next().unwrap_or(&conf.json_field);
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = hunter::printJSONField(&client, path, field, &conf).await;
+        return audit::record(&conf, "pretty-json", Some(path), result);
+    }
+
+    if let Some(mut args) = matches.values_of("has-field")
+    {
+        let path = args.next().unwrap();
+        let key = args.next().unwrap();
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = audit::record(&conf, "has-field", Some(path),
+                                   client.get(path).await);
+        // This is a predicate for scripts, so only the exit code tells
+        // the answer.
+        match result
+        {
+            Ok(data) if data.contains_key(key) => return Ok(()),
+            Ok(_) => std::process::exit(error::EXIT_NO_FIELD),
+            Err(Error::NotFoundError(_)) =>
+                std::process::exit(error::EXIT_NOT_FOUND),
+            Err(e) => return Err(e),
+        }
+    }
+
+    if let Some(mut args) = matches.values_of("replace-field")
+    {
+        let path = args.next().unwrap();
+        let assignment = args.next().unwrap();
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = hunter::replaceField(&client, path, assignment, &conf)
+            .await;
+        return audit::record(&conf, "replace-field", Some(path), result);
+    }
+
+    if let Some(path) = matches.value_of("touch")
+    {
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = hunter::touch(&client, path, matches.is_present("yes"),
+                                   &conf).await;
+        return audit::record(&conf, "touch", Some(path), result);
+    }
+
+    if let Some(pattern) = matches.value_of("prune")
+    {
+        let matcher = makeMatcher(matches, pattern)?;
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return hunter::prune(&client, &matcher, matches.is_present("yes"),
+                             &conf).await;
+    }
+
+    if let Some(pattern) = matches.value_of("query")
+    {
+        let matcher = makeMatcher(matches, pattern)?;
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return hunter::query(&client, &matcher, matches.is_present("reveal"),
+                             &conf).await;
+    }
+
+    if matches.is_present("stats")
+    {
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return hunter::printStats(&client, matches.is_present("detailed"),
+                                  matches.is_present("json")).await;
+    }
+
+    if matches.is_present("expiring")
+    {
+        let days = match matches.value_of("expiring")
+        {
+            Some(d) => d.parse().map_err(
+                |_| rterr!("Invalid number of days: {}", d))?,
+            None => conf.rotation_warn_days,
+        };
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return hunter::printExpiring(&client, days,
+                                     matches.is_present("show-missing")).await;
+    }
+
+    if matches.is_present("self-test")
+    {
+        if !matches.is_present("yes")
+        {
+            return Err(rterr!("The self-test writes to Vault. Use --yes to \
+                               run it"));
+        }
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return hunter::selfTest(&client).await;
+    }
+
+    if let Some(file) = matches.value_of("import")
+    {
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let result = hunter::importXML(&client, file,
+                                       matches.is_present("dry-run"), &conf)
+            .await;
+        return audit::record(&conf, "import", None, result);
+    }
+
+    let export_opts = hunter::ExportOptions {
+        plaintext: matches.is_present("plaintext"),
+        with_metadata: matches.is_present("with-metadata"),
+        merge: matches.is_present("merge-export"),
+        verify: matches.is_present("verify-export"),
+        diff: matches.is_present("diff-export"),
+        dedupe: matches.is_present("dedupe-export"),
+    };
+    if matches.is_present("export-to-stdout")
+    {
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        let start = std::time::Instant::now();
+        let result = hunter::writeExport(&client, &conf, &mut std::io::stdout(),
+                                         &export_opts).await;
+        if let Some(file) = &conf.metrics_file
+        {
+            if let Err(e) = hunter::writeMetrics(file, &result,
+                                                 start.elapsed())
+            {
+                eprintln!("Warning: {}", e.message());
+            }
+        }
+        return audit::record(&conf, "export", None, result.map(|_| ()));
+    }
+
+    if let Some(template) = matches.value_of("export-template")
+    {
+        let matcher = matches.value_of("PATTERN")
+            .map(|p| makeMatcher(matches, p)).transpose()?;
+        let strict = matches.is_present("template-strict");
+        if matches.is_present("offline")
+        {
+            let xml = hunter::loadOffline(&conf)?;
+            return hunter::exportTemplate(&xml, matcher.as_ref(), template,
+                                          strict, &conf).await;
+        }
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return hunter::exportTemplate(&client, matcher.as_ref(), template,
+                                      strict, &conf).await;
+    }
+
     // Key lookup
-    if !matches.is_present("PATTERN")
+    let pattern = if let Some(p) = matches.value_of("PATTERN")
     {
-        return Err(rterr!("Expecting PATTERN"));
+        p
     }
+    else
+    {
+        return Err(rterr!("Expecting PATTERN"));
+    };
+    let matcher = makeMatcher(matches, pattern)?;
+    let search_opts = hunter::SearchOptions {
+        target: matches.value_of("type").unwrap().parse()?,
+        sort: matches.value_of("sort").unwrap().parse()?,
+        values: matches.is_present("search-values"),
+    };
+    let reveal_opts = hunter::RevealOptions {
+        format: matches.value_of("format").unwrap().parse()?,
+        reveal: matches.is_present("reveal"),
+        copy_json: matches.is_present("copy-json"),
+        fill: matches.is_present("fill"),
+        copy_all: matches.is_present("copy-all"),
+        show_all: matches.is_present("show-all"),
+        field: if matches.is_present("copy-username")
+        {
+            Some(conf.username_field.clone())
+        }
+        else
+        {
+            matches.value_of("copy-field").map(|f| f.to_owned())
+        },
+        raw: matches.is_present("raw"),
+        strict: matches.is_present("strict"),
+        output_file: matches.value_of("output-file").map(|f| f.to_owned()),
+        force: matches.is_present("force"),
+    };
 
+    if config::isVaultURL(pattern)
+    {
+        let (_, path) = config::parseVaultURL(pattern)?;
+        let mut client = vault_client::Client::new(&conf)?;
+        client.login().await?;
+        return hunter::revealExact(&client, &path, &conf, &reveal_opts)
+            .await;
+    }
+    if matches.is_present("merge-profiles")
+    {
+        let mut profiles: Vec<(String, config::Config)> = Vec::new();
+        for name in conf.profiles.keys()
+        {
+            match conf.loadProfile(name)
+            {
+                Ok(c) => profiles.push((name.clone(), c)),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        let mut sources = Vec::new();
+        for (name, c) in std::iter::once(("default", &conf))
+            .chain(profiles.iter().map(|(n, c)| (n.as_str(), c)))
+        {
+            if let Some(source) = loginProfile(name, c).await
+            {
+                sources.push((name.to_owned(), source));
+            }
+        }
+        let merged = source::MergedSource::new(sources);
+        return hunter::searchReveal(&merged, &matcher, &search_opts, &conf,
+                                    &reveal_opts).await;
+    }
+    if matches.is_present("offline")
+    {
+        let xml = hunter::loadOffline(&conf)?;
+        return hunter::searchReveal(&xml, &matcher,
+                                    &search_opts, &conf, &reveal_opts).await;
+    }
     let mut client = vault_client::Client::new(&conf)?;
     client.login().await?;
-    if conf.local_xml.is_some()
+    if conf.local_xml.is_some() && !matches.is_present("no-export")
     {
-        hunter::exportPasswords(&client, &conf).await?;
+        hunter::exportPasswords(&client, &conf, &export_opts).await?;
     }
-    hunter::searchReveal(&client, matches.value_of("PATTERN").unwrap(), &conf)
-        .await
+    hunter::searchReveal(&client, &matcher,
+                         &search_opts, &conf, &reveal_opts).await
 }
@@ -0,0 +1,197 @@
+//! A minimal HTTP server standing in for Vault in tests.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::Config;
+
+/// A request received by the mock.
+#[derive(Clone, Debug)]
+pub struct Request
+{
+    pub method: String,
+    /// Path of the request, without the query.
+    pub path: String,
+    /// The bearer token, if any.
+    pub token: Option<String>,
+    pub body: String,
+}
+
+impl Request
+{
+    /// The body parsed as JSON, or null if it is empty.
+    pub fn json(&self) -> serde_json::Value
+    {
+        serde_json::from_str(&self.body).unwrap_or_default()
+    }
+}
+
+type Handler = dyn Fn(&Request) -> (u16, String) + Send + Sync;
+
+struct State
+{
+    handler: Box<Handler>,
+    delay: Duration,
+    requests: Mutex<Vec<Request>>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+pub struct MockVault
+{
+    pub end_point: String,
+    /// A runtime info file of its own, so that tests do not touch the
+    /// one of the user.
+    pub cache_path: String,
+    state: Arc<State>,
+}
+
+/// Counts the servers started, to give each its own cache file.
+static SERVERS: AtomicUsize = AtomicUsize::new(0);
+
+impl MockVault
+{
+    /// Start a server that answers each request with the status and
+    /// JSON body returned by `handler`.
+    pub async fn start<F>(handler: F) -> Self
+        where F: Fn(&Request) -> (u16, String) + Send + Sync + 'static
+    {
+        Self::withDelay(handler, Duration::ZERO).await
+    }
+
+    /// Like `start`, but wait `delay` before answering each request.
+    pub async fn withDelay<F>(handler: F, delay: Duration) -> Self
+        where F: Fn(&Request) -> (u16, String) + Send + Sync + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let end_point = format!("http://{}/", listener.local_addr().unwrap());
+        let state = Arc::new(State {
+            handler: Box::new(handler),
+            delay,
+            requests: Mutex::new(Vec::new()),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+        });
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            loop
+            {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve(stream, server_state.clone()));
+            }
+        });
+        let cache_path = std::env::temp_dir().join(format!(
+            "vault-hunter-test-{}-{}.json", std::process::id(),
+            SERVERS.fetch_add(1, Ordering::SeqCst)));
+        Self { end_point, state,
+               cache_path: cache_path.to_string_lossy().into_owned() }
+    }
+
+    /// A config that talks to this server as the default user.
+    pub fn config(&self) -> Config
+    {
+        let mut conf = Config::default();
+        conf.end_point = self.end_point.clone();
+        conf.cache_path = Some(self.cache_path.clone());
+        conf.quiet = true;
+        conf
+    }
+
+    /// The requests received so far, in order.
+    pub fn requests(&self) -> Vec<Request>
+    {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// The largest number of requests that were handled at once.
+    pub fn maxInFlight(&self) -> usize
+    {
+        self.state.max_in_flight.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockVault
+{
+    fn drop(&mut self)
+    {
+        let _ = std::fs::remove_file(&self.cache_path);
+    }
+}
+
+async fn readRequest(stream: &mut TcpStream) -> Option<Request>
+{
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop
+    {
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n")
+        {
+            break i + 4;
+        }
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0
+        {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..header_end]).into_owned();
+    let mut words = head.lines().next()?.split_whitespace();
+    let method = words.next()?.to_owned();
+    let target = words.next()?;
+    let path = target.split('?').next()?.to_owned();
+    let header = |name: &str| head.lines().filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim().to_owned());
+    let token = header("authorization")
+        .and_then(|v| v.strip_prefix("Bearer ").map(str::to_owned))
+        .or_else(|| header("x-vault-token"));
+    let length: usize = header("content-length")
+        .and_then(|v| v.parse().ok()).unwrap_or(0);
+    while buf.len() < header_end + length
+    {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0
+        {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let body = String::from_utf8_lossy(&buf[header_end..]).into_owned();
+    Some(Request { method, path, token, body })
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<State>)
+{
+    let req = if let Some(r) = readRequest(&mut stream).await
+    {
+        r
+    }
+    else
+    {
+        return;
+    };
+    let now = state.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    state.max_in_flight.fetch_max(now, Ordering::SeqCst);
+    state.requests.lock().unwrap().push(req.clone());
+    // Vault redirects these with a 301, which turns a write into a
+    // GET. Fail them, so that tests catch malformed URLs.
+    let (status, body) = if req.path.contains("//")
+    {
+        (400, String::from(r#"{"errors": ["repeated slashes in path"]}"#))
+    }
+    else
+    {
+        (state.handler)(&req)
+    };
+    tokio::time::sleep(state.delay).await;
+    state.in_flight.fetch_sub(1, Ordering::SeqCst);
+    let response = format!("HTTP/1.1 {} Mock\r\nContent-Type: application/json\
+                            \r\nContent-Length: {}\r\nConnection: close\r\n\
+                            \r\n{}", status, body.len(), body);
+    let _ = stream.write_all(response.as_bytes()).await;
+}
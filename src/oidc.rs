@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::Config;
+use crate::error::Error;
+
+/// Path of the redirect URI of browser login.
+const CALLBACK_PATH: &str = "/oidc/callback";
+
+/// The redirect URI of browser login, which has to be allowed in the
+/// OIDC role. The host is `oidc_callback_address`, except that a
+/// loopback or unspecified address is `localhost`, which is what
+/// roles usually allow.
+pub fn redirectURI(conf: &Config) -> String
+{
+    let address = &conf.oidc_callback_address;
+    let host = match address.parse::<std::net::IpAddr>()
+    {
+        Ok(ip) if ip.is_loopback() || ip.is_unspecified() =>
+            String::from("localhost"),
+        Ok(std::net::IpAddr::V6(ip)) => format!("[{}]", ip),
+        _ => address.clone(),
+    };
+    format!("http://{}:{}{}", host, conf.oidc_callback_port, CALLBACK_PATH)
+}
+
+/// Open `url` in the default browser. Failing to is not an error,
+/// because the URL is also printed for the user.
+pub fn openBrowser(url: &str)
+{
+    let mut cmd = match std::env::consts::OS
+    {
+        "macos" => Command::new("open"),
+        "windows" =>
+        {
+            let mut c = Command::new("cmd");
+            c.args(["/C", "start", ""]);
+            c
+        },
+        _ => Command::new("xdg-open"),
+    };
+    let _ = cmd.arg(url).stdin(Stdio::null()).stdout(Stdio::null())
+        .stderr(Stdio::null()).spawn();
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str)
+{
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; \
+                            charset=utf-8\r\nContent-Length: {}\r\n\
+                            Connection: close\r\n\r\n{}",
+                           status, body.len(), body);
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+/// Read the request line from `stream`, and return the query
+/// parameters if it is a request for the callback.
+async fn readCallback(stream: &mut TcpStream) ->
+    Option<HashMap<String, String>>
+{
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(2).any(|w| w == b"\r\n") && buf.len() < 8192
+    {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0
+        {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let request = String::from_utf8_lossy(&buf);
+    let target = request.lines().next()?.split_whitespace().nth(1)?;
+    let url = reqwest::Url::parse(&format!("http://localhost{}", target))
+        .ok()?;
+    if url.path() != CALLBACK_PATH
+    {
+        return None;
+    }
+    Some(url.query_pairs().into_owned().collect())
+}
+
+async fn acceptCallback(listener: &TcpListener) ->
+    Result<(String, String), Error>
+{
+    loop
+    {
+        let (mut stream, _) = listener.accept().await.map_err(
+            |e| rterr!("Failed to accept the OIDC callback: {}", e))?;
+        // Browsers also ask for things like the favicon.
+        let params = if let Some(p) = readCallback(&mut stream).await
+        {
+            p
+        }
+        else
+        {
+            respond(&mut stream, "404 Not Found", "Not found").await;
+            continue;
+        };
+        if let Some(e) = params.get("error")
+        {
+            respond(&mut stream, "400 Bad Request",
+                    "Login failed. You can close this window.").await;
+            return Err(error!(AuthError, "OIDC login failed: {} {}", e,
+                              params.get("error_description")
+                              .map_or("", |d| d.as_str())));
+        }
+        match (params.get("code"), params.get("state"))
+        {
+            (Some(code), Some(state)) =>
+            {
+                respond(&mut stream, "200 OK",
+                        "Login complete. You can close this window.").await;
+                return Ok((code.clone(), state.clone()));
+            },
+            _ => respond(&mut stream, "400 Bad Request",
+                         "Missing code or state").await,
+        }
+    }
+}
+
+/// Start listening for the redirect of browser login. This should be
+/// done before opening the browser.
+pub async fn listen(conf: &Config) -> Result<TcpListener, Error>
+{
+    TcpListener::bind(
+        (conf.oidc_callback_address.as_str(), conf.oidc_callback_port))
+        .await.map_err(|e| rterr!("Failed to listen on {}:{}: {}",
+                                  conf.oidc_callback_address,
+                                  conf.oidc_callback_port, e))
+}
+
+/// Wait for the redirect of the OIDC provider after browser login,
+/// and return the `code` and `state` in it. Fail if the login is not
+/// completed within `oidc_timeout_secs`.
+pub async fn waitForCallback(listener: &TcpListener, conf: &Config) ->
+    Result<(String, String), Error>
+{
+    tokio::time::timeout(Duration::from_secs(conf.oidc_timeout_secs),
+                         acceptCallback(listener)).await
+        .map_err(|_| error!(AuthError, "Timed out waiting for the browser \
+                                        login"))?
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn redirectURIFollowsCallbackAddress()
+    {
+        let mut conf = Config::default();
+        let port = conf.oidc_callback_port;
+        let uri = |conf: &Config| redirectURI(conf)
+            .replace(&format!(":{}/oidc/callback", port), "");
+        assert_eq!(uri(&conf), "http://localhost");
+        for (address, host) in [("0.0.0.0", "localhost"), ("::1", "localhost"),
+                                ("192.168.1.5", "192.168.1.5"),
+                                ("fd00::1", "[fd00::1]"),
+                                ("myhost.lan", "myhost.lan")]
+        {
+            conf.oidc_callback_address = String::from(address);
+            assert_eq!(uri(&conf), format!("http://{}", host));
+        }
+    }
+}
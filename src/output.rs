@@ -0,0 +1,79 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Print an informational message to stderr, unless quiet mode is
+/// on. The first argument is the config. Stdout is kept for actual
+/// results, so that they can be piped. Errors should not go through
+/// this.
+#[macro_export]
+macro_rules! info
+{
+    ($conf:expr, $($arg:tt)+) =>
+    {
+        if !$conf.quiet
+        {
+            eprintln!($($arg)+);
+        }
+    };
+}
+
+const SPINNER_FRAMES: &[char] = &['|', '/', '-', '\\'];
+
+/// A spinner on stderr for slow operations. It is only shown if
+/// stderr is a terminal, quiet mode and tracing are off, and the
+/// operation takes more than a moment. The line is cleared when the
+/// spinner is dropped, so drop it before printing anything.
+pub struct Spinner
+{
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Spinner
+{
+    pub fn start(message: &str, conf: &Config) -> Self
+    {
+        use std::io::IsTerminal;
+        if conf.quiet || conf.trace || !std::io::stderr().is_terminal()
+        {
+            return Self { stop: None, thread: None };
+        }
+        let (stop, stopped) = mpsc::channel::<()>();
+        let message = message.to_owned();
+        let thread = std::thread::spawn(move || {
+            let still_running = |ms| matches!(
+                stopped.recv_timeout(Duration::from_millis(ms)),
+                Err(mpsc::RecvTimeoutError::Timeout));
+            // Fast operations finish before anything is shown.
+            if !still_running(200)
+            {
+                return;
+            }
+            for frame in SPINNER_FRAMES.iter().cycle()
+            {
+                eprint!("\r{} {}", frame, message);
+                if !still_running(100)
+                {
+                    break;
+                }
+            }
+            eprint!("\r\x1b[K");
+        });
+        Self { stop: Some(stop), thread: Some(thread) }
+    }
+}
+
+impl Drop for Spinner
+{
+    fn drop(&mut self)
+    {
+        // Closing the channel stops the thread.
+        self.stop.take();
+        if let Some(thread) = self.thread.take()
+        {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,66 +1,153 @@
-use std::fs::File;
-use std::io::BufReader;
+use std::path::{Path, PathBuf};
 
 use crate::error::Error;
 use crate::config::Config;
+use crate::vault_client::StringMap;
+
+/// A file format of the runtime info. Unset keys are not stored.
+trait RuntimeFormat
+{
+    fn parse(&self, content: &str) -> Result<StringMap, Error>;
+    fn serialize(&self, data: &StringMap) -> Result<String, Error>;
+}
+
+struct JSONFormat;
+
+impl RuntimeFormat for JSONFormat
+{
+    fn parse(&self, content: &str) -> Result<StringMap, Error>
+    {
+        let data: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(content).map_err(
+                |_| rterr!("Failed to read JSON from runtime info file"))?;
+        let mut result = StringMap::new();
+        for (key, value) in data
+        {
+            match value
+            {
+                // Older versions store unset keys as null.
+                serde_json::Value::Null => {},
+                serde_json::Value::String(s) => { result.insert(key, s); },
+                _ => return Err(rterr!("Invalid runtime info")),
+            }
+        }
+        Ok(result)
+    }
+
+    fn serialize(&self, data: &StringMap) -> Result<String, Error>
+    {
+        serde_json::to_string_pretty(data).map_err(
+            |_| rterr!("Failed to write runtime info"))
+    }
+}
+
+struct TOMLFormat;
+
+impl RuntimeFormat for TOMLFormat
+{
+    fn parse(&self, content: &str) -> Result<StringMap, Error>
+    {
+        toml::from_str(content).map_err(
+            |_| rterr!("Failed to read TOML from runtime info file"))
+    }
+
+    fn serialize(&self, data: &StringMap) -> Result<String, Error>
+    {
+        toml::to_string(data).map_err(
+            |_| rterr!("Failed to write runtime info"))
+    }
+}
+
+fn format(config: &Config) -> Result<Box<dyn RuntimeFormat>, Error>
+{
+    match config.runtime_format.as_str()
+    {
+        "json" => Ok(Box::new(JSONFormat)),
+        "toml" => Ok(Box::new(TOMLFormat)),
+        f => Err(error!(ConfigError, "Invalid runtime_format: {}", f)),
+    }
+}
+
+fn readFile(path: &Path, format: &dyn RuntimeFormat) ->
+    Result<StringMap, Error>
+{
+    let content = std::fs::read_to_string(path).map_err(
+        |_| rterr!("Failed to open runtime info file"))?;
+    format.parse(&content)
+}
+
+fn writeFile(path: &Path, format: &dyn RuntimeFormat, data: &StringMap) ->
+    Result<(), Error>
+{
+    std::fs::write(path, format.serialize(data)?).map_err(
+        |_| rterr!("Failed to open runtime info file"))
+}
+
+/// Read the runtime info file. Return None if the file path cannot
+/// be determined or the file does not exist. If the format is TOML
+/// and only a JSON file from an older version exists next to it,
+/// migrate the JSON file.
+fn readRuntimeInfo(config: &Config) ->
+    Result<Option<(PathBuf, StringMap)>, Error>
+{
+    let file_path = if let Some(p) = config.runtimeInfoPath()
+    {
+        p
+    }
+    else
+    {
+        return Ok(None);
+    };
+    let format = format(config)?;
+    if file_path.exists()
+    {
+        let data = readFile(&file_path, format.as_ref())?;
+        return Ok(Some((file_path, data)));
+    }
+
+    let json_path = file_path.with_extension("json");
+    if config.runtime_format == "toml" && json_path.exists()
+    {
+        let data = readFile(&json_path, &JSONFormat)?;
+        writeFile(&file_path, format.as_ref(), &data)?;
+        std::fs::remove_file(&json_path).map_err(
+            |_| rterr!("Failed to remove old runtime info file"))?;
+        return Ok(Some((file_path, data)));
+    }
+    Ok(None)
+}
 
 /// Set a key-value in the runtime info file. If the file path
 /// cannot be determined, do nothing and return Ok.
 pub fn setRuntimeInfo(key: &str, value: Option<&str>, config: &Config) ->
     Result<(), Error>
 {
-    let mut data = serde_json::Value::default();
-    if let Some(file_path) = config.runtimeInfoPath()
+    let (file_path, mut data) = match readRuntimeInfo(config)?
     {
-        if file_path.exists()
+        Some(info) => info,
+        None => match config.runtimeInfoPath()
         {
-            let file = File::open(&file_path).map_err(
-                |_| rterr!("Failed to open runtime info file"))?;
-            let reader = BufReader::new(file);
-            data = serde_json::from_reader(reader).map_err(
-                |_| rterr!("Failed to read JSON from runtime info file"))?;
-        }
-        if let Some(v) = value
-        {
-            data[key] = serde_json::Value::String(v.to_owned());
-        }
-        else
-        {
-            data[key] = serde_json::Value::Null;
-        }
-        let file = File::create(file_path).map_err(
-            |_| rterr!("Failed to open runtime info file"))?;
-        serde_json::to_writer_pretty(file, &data).map_err(
-            |_| rterr!("Failed to write runtime info"))?;
+            Some(p) => (p, StringMap::new()),
+            None => return Ok(()),
+        },
+    };
+    if let Some(v) = value
+    {
+        data.insert(key.to_owned(), v.to_owned());
+    }
+    else
+    {
+        data.shift_remove(key);
     }
-    Ok(())
+    writeFile(&file_path, format(config)?.as_ref(), &data)
 }
 
 pub fn getRuntimeInfo(key: &str, config: &Config) ->
     Result<Option<String>, Error>
 {
-    if let Some(file_path) = config.runtimeInfoPath()
+    if let Some((_, data)) = readRuntimeInfo(config)?
     {
-        if file_path.exists()
-        {
-            let file = File::open(file_path).map_err(
-                |_| rterr!("Failed to open runtime info file"))?;
-            let reader = BufReader::new(file);
-            let data: serde_json::Value = serde_json::from_reader(reader)
-                .map_err(|_| error!(
-                    RuntimeError,
-                    "Failed to read JSON from runtime info file"))?;
-            match data.get(key)
-            {
-                None => Ok(None),
-                Some(v) => v.as_str().map(|s| Some(s.to_owned())).ok_or(
-                    rterr!("Invalid runtime info")),
-            }
-        }
-        else
-        {
-            Err(rterr!("No runtime info available"))
-        }
+        Ok(data.get(key).cloned())
     }
     else
     {
@@ -0,0 +1,298 @@
+use chrono::prelude::*;
+use indexmap::IndexMap;
+
+use crate::error::Error;
+use crate::vault_client::{stringifyEntry, Client, KeyOrDir, Matcher, Path,
+                          SearchResult, SearchTarget, StringMap};
+use crate::xml;
+
+fn toPath(path: &str) -> Path
+{
+    let mut p = Path::new();
+    for comp in path.split('/').filter(|c| !c.is_empty())
+    {
+        p.push(comp);
+    }
+    p
+}
+
+/// Entries parsed from an XML export, to look up passwords without
+/// Vault.
+pub struct XmlSource
+{
+    entries: IndexMap<String, xml::Entry>,
+}
+
+impl XmlSource
+{
+    pub fn new(entries: Vec<xml::Entry>) -> Self
+    {
+        Self {
+            entries: entries.into_iter().map(|e| (e.path.clone(), e))
+                .collect(),
+        }
+    }
+}
+
+/// Where search and reveal get the entries from. Implemented by
+/// `Client` for Vault, and by `XmlSource` for a local export.
+#[async_trait::async_trait]
+pub trait SecretSource: Sync
+{
+    /// List the items in the directory at `path`.
+    async fn list(&self, path: &str) -> Result<Vec<KeyOrDir>, Error>;
+
+    /// Retrieve the entry at `path` as it is stored.
+    async fn getRaw(&self, path: &str) ->
+        Result<serde_json::Map<String, serde_json::Value>, Error>;
+
+    /// Retrieve the entry at `path` with all values as strings.
+    async fn get(&self, path: &str) -> Result<StringMap, Error>
+    {
+        Ok(stringifyEntry(self.getRaw(path).await?))
+    }
+
+    /// Find the keys and directories matching `matcher`.
+    async fn search(&self, matcher: &Matcher, target: SearchTarget) ->
+        Result<SearchResult, Error>;
+
+    /// Paths of all the entries.
+    async fn keys(&self) -> Result<Vec<Path>, Error>;
+
+    /// When the entry at `path` was last updated, if known.
+    async fn updatedTime(&self, path: &str) ->
+        Result<Option<DateTime<Utc>>, Error>;
+}
+
+#[async_trait::async_trait]
+impl SecretSource for Client<'_>
+{
+    async fn list(&self, path: &str) -> Result<Vec<KeyOrDir>, Error>
+    {
+        Client::list(self, path).await
+    }
+
+    async fn getRaw(&self, path: &str) ->
+        Result<serde_json::Map<String, serde_json::Value>, Error>
+    {
+        Client::getRaw(self, path).await
+    }
+
+    async fn search(&self, matcher: &Matcher, target: SearchTarget) ->
+        Result<SearchResult, Error>
+    {
+        Client::search(self, matcher, target).await
+    }
+
+    async fn keys(&self) -> Result<Vec<Path>, Error>
+    {
+        let mut paths: Vec<Path> = Vec::new();
+        self.walk(&Path::new(), |path, item| if let KeyOrDir::Key(_) = item
+        {
+            paths.push(path.clone());
+        }).await?;
+        Ok(paths)
+    }
+
+    async fn updatedTime(&self, path: &str) ->
+        Result<Option<DateTime<Utc>>, Error>
+    {
+        Ok(self.metadata(path).await?.updated_time.parse().ok())
+    }
+}
+
+#[async_trait::async_trait]
+impl SecretSource for XmlSource
+{
+    async fn list(&self, path: &str) -> Result<Vec<KeyOrDir>, Error>
+    {
+        let prefix = if path.is_empty()
+        {
+            String::new()
+        }
+        else
+        {
+            format!("{}/", path.trim_end_matches('/'))
+        };
+        let mut items: Vec<KeyOrDir> = Vec::new();
+        for entry_path in self.entries.keys()
+        {
+            let rest = if let Some(rest) = entry_path.strip_prefix(&prefix)
+            {
+                rest
+            }
+            else
+            {
+                continue;
+            };
+            let item = if let Some((dir, _)) = rest.split_once('/')
+            {
+                KeyOrDir::Dir(dir.to_owned())
+            }
+            else
+            {
+                KeyOrDir::Key(rest.to_owned())
+            };
+            if !items.contains(&item)
+            {
+                items.push(item);
+            }
+        }
+        if items.is_empty() && !path.is_empty()
+        {
+            return Err(error!(NotFoundError, "No directory at {}", path));
+        }
+        Ok(items)
+    }
+
+    async fn getRaw(&self, path: &str) ->
+        Result<serde_json::Map<String, serde_json::Value>, Error>
+    {
+        let entry = self.entries.get(path).ok_or_else(
+            || error!(NotFoundError, "No entry at {}", path))?;
+        Ok(entry.data.iter().map(
+            |(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+           .collect())
+    }
+
+    async fn search(&self, matcher: &Matcher, target: SearchTarget) ->
+        Result<SearchResult, Error>
+    {
+        let mut result = SearchResult::default();
+        for entry_path in self.entries.keys()
+        {
+            let comps: Vec<&str> = entry_path.split('/').collect();
+            let mut path = Path::new();
+            for (i, comp) in comps.iter().enumerate()
+            {
+                path.push(comp);
+                if i + 1 == comps.len()
+                {
+                    if target != SearchTarget::Dir
+                        && matcher.matches(comp, &path)
+                    {
+                        result.keys.push(path.clone());
+                    }
+                }
+                else if target != SearchTarget::Key
+                    && matcher.matches(comp, &path)
+                    && !result.dirs.contains(&path)
+                {
+                    result.dirs.push(path.clone());
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    async fn keys(&self) -> Result<Vec<Path>, Error>
+    {
+        Ok(self.entries.keys().map(|p| toPath(p)).collect())
+    }
+
+    /// The creation time of the exported version, which is only known
+    /// if the export has metadata.
+    async fn updatedTime(&self, path: &str) ->
+        Result<Option<DateTime<Utc>>, Error>
+    {
+        Ok(self.entries.get(path).and_then(|e| e.created_time.as_ref())
+           .and_then(|t| t.parse().ok()))
+    }
+}
+
+/// Several sources searched together, like the profiles in
+/// `--merge-profiles`. Paths are prefixed with the name of their
+/// source. A source that fails to search is reported and skipped, so
+/// that the others still give results.
+pub struct MergedSource<'a>
+{
+    sources: Vec<(String, Box<dyn SecretSource + 'a>)>,
+}
+
+impl<'a> MergedSource<'a>
+{
+    pub fn new(sources: Vec<(String, Box<dyn SecretSource + 'a>)>) -> Self
+    {
+        Self { sources }
+    }
+
+    /// Find the source of `path`, and the path within that source.
+    fn split<'p>(&self, path: &'p str) ->
+        Result<(&(dyn SecretSource + 'a), &'p str), Error>
+    {
+        let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+        self.sources.iter().find(|(n, _)| n == name)
+            .map(|(_, source)| (source.as_ref(), rest))
+            .ok_or_else(|| error!(NotFoundError, "No profile {}", name))
+    }
+}
+
+fn prefixed(name: &str, path: &Path) -> Path
+{
+    toPath(&format!("{}/{}", name, path))
+}
+
+#[async_trait::async_trait]
+impl SecretSource for MergedSource<'_>
+{
+    async fn list(&self, path: &str) -> Result<Vec<KeyOrDir>, Error>
+    {
+        if path.is_empty()
+        {
+            return Ok(self.sources.iter()
+                      .map(|(name, _)| KeyOrDir::Dir(name.clone())).collect());
+        }
+        let (source, rest) = self.split(path)?;
+        source.list(rest).await
+    }
+
+    async fn getRaw(&self, path: &str) ->
+        Result<serde_json::Map<String, serde_json::Value>, Error>
+    {
+        let (source, rest) = self.split(path)?;
+        source.getRaw(rest).await
+    }
+
+    async fn search(&self, matcher: &Matcher, target: SearchTarget) ->
+        Result<SearchResult, Error>
+    {
+        let mut result = SearchResult::default();
+        for (name, source) in &self.sources
+        {
+            match source.search(matcher, target).await
+            {
+                Ok(found) =>
+                {
+                    result.keys.extend(
+                        found.keys.iter().map(|p| prefixed(name, p)));
+                    result.dirs.extend(
+                        found.dirs.iter().map(|p| prefixed(name, p)));
+                },
+                Err(e) => eprintln!("{}: {}", name, e),
+            }
+        }
+        Ok(result)
+    }
+
+    async fn keys(&self) -> Result<Vec<Path>, Error>
+    {
+        let mut paths = Vec::new();
+        for (name, source) in &self.sources
+        {
+            match source.keys().await
+            {
+                Ok(keys) =>
+                    paths.extend(keys.iter().map(|p| prefixed(name, p))),
+                Err(e) => eprintln!("{}: {}", name, e),
+            }
+        }
+        Ok(paths)
+    }
+
+    async fn updatedTime(&self, path: &str) ->
+        Result<Option<DateTime<Utc>>, Error>
+    {
+        let (source, rest) = self.split(path)?;
+        source.updatedTime(rest).await
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Instant;
+
+use reqwest_middleware::{Middleware, Next};
+
+/// Headers whose values are never printed in traces.
+const SECRET_HEADERS: &[&str] = &["authorization", "x-vault-token"];
+
+/// Middleware that prints the method, URL, headers, status, and
+/// timing of each request to stderr. Header values that carry
+/// credentials are redacted, and bodies are never printed, because
+/// they may contain passwords or tokens.
+pub struct TraceMiddleware;
+
+fn printHeaders(prefix: &str, headers: &reqwest::header::HeaderMap)
+{
+    for (name, value) in headers
+    {
+        if SECRET_HEADERS.contains(&name.as_str())
+        {
+            eprintln!("{} {}: <redacted>", prefix, name);
+        }
+        else
+        {
+            eprintln!("{} {}: {}", prefix, name,
+                      value.to_str().unwrap_or("<binary>"));
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for TraceMiddleware
+{
+    async fn handle(&self, req: reqwest::Request,
+                    extensions: &mut http::Extensions, next: Next<'_>) ->
+        reqwest_middleware::Result<reqwest::Response>
+    {
+        eprintln!("> {} {}", req.method(), req.url());
+        printHeaders(">", req.headers());
+        if req.body().is_some()
+        {
+            eprintln!("> <body redacted>");
+        }
+        let start = Instant::now();
+        let res = next.run(req, extensions).await;
+        let elapsed = start.elapsed().as_millis();
+        match &res
+        {
+            Ok(r) =>
+            {
+                eprintln!("< {} ({} ms)", r.status(), elapsed);
+                printHeaders("<", r.headers());
+            },
+            Err(e) => eprintln!("< failed after {} ms: {}", elapsed, e),
+        }
+        res
+    }
+}
@@ -3,26 +3,139 @@ use std::io::Read;
 use std::fmt;
 use std::str::FromStr;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
+use indexmap::IndexMap;
+use serde::Deserialize;
 use serde_json::{self, json};
-use reqwest;
-use rpassword;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
 use crate::error::Error;
+use crate::crypt;
 use crate::config;
+use crate::oidc;
+use crate::output::Spinner;
 use crate::runtime_info::{setRuntimeInfo, getRuntimeInfo};
 
-pub type StringMap = HashMap<String, String>;
+/// Fields of an entry, in the order they are stored.
+pub type StringMap = IndexMap<String, String>;
 
-fn readCert(filename: &str) -> Result<reqwest::Certificate, Error>
+/// Reading the body of a response with a size limit, so that a huge
+/// body is an error instead of exhausting the memory.
+trait LimitedBody
+{
+    async fn jsonLimited(self, limit: usize) ->
+        Result<serde_json::Value, Error>;
+}
+
+impl LimitedBody for reqwest::Response
+{
+    async fn jsonLimited(mut self, limit: usize) ->
+        Result<serde_json::Value, Error>
+    {
+        let too_large = || rterr!("Response is larger than \
+                                   max_response_bytes ({} bytes)", limit);
+        if self.content_length().is_some_and(|n| n > limit as u64)
+        {
+            return Err(too_large());
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = self.chunk().await.map_err(
+            |e| error!(HTTPError, "Failed to read response: {}", e))?
+        {
+            if body.len() + chunk.len() > limit
+            {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        serde_json::from_slice(&body).map_err(
+            |_| rterr!("Failed to parse JSON"))
+    }
+}
+
+/// Convert an entry as stored in Vault to strings. Values that are
+/// not strings are kept as their JSON text, e.g. `42` or `true`.
+pub fn stringifyEntry(data: serde_json::Map<String, serde_json::Value>) ->
+    StringMap
+{
+    data.into_iter().map(|(key, value)| match value
+    {
+        serde_json::Value::String(s) => (key, s),
+        v => (key, v.to_string()),
+    }).collect()
+}
+
+/// Convert string fields to an entry as stored in Vault.
+pub fn rawEntry(data: &StringMap) ->
+    serde_json::Map<String, serde_json::Value>
+{
+    data.iter().map(|(key, value)|
+                    (key.clone(), serde_json::Value::String(value.clone())))
+        .collect()
+}
+
+/// Read a password from `path`, typically a FIFO written by a
+/// graphical prompt. The password is the first line; the newline is
+/// optional. Opening a FIFO blocks until the other side opens it for
+/// writing, and reading stops at the first newline or when the
+/// writer closes it.
+fn readAskpass(path: &str) -> Result<String, Error>
+{
+    use std::io::BufRead;
+    let file = std::fs::File::open(path).map_err(
+        |e| rterr!("Failed to open {}: {}", path, e))?;
+    let mut line = String::new();
+    std::io::BufReader::new(file).read_line(&mut line).map_err(
+        |e| rterr!("Failed to read password from {}: {}", path, e))?;
+    let pass = line.strip_suffix('\n').unwrap_or(&line);
+    Ok(pass.strip_suffix('\r').unwrap_or(pass).to_owned())
+}
+
+/// Read all the certs in a PEM file, which may be a bundle of
+/// several certs.
+fn readCert(filename: &str) -> Result<Vec<reqwest::Certificate>, Error>
 {
     let mut buf = Vec::new();
     File::open(filename)
         .map_err(|_| error!(RuntimeError, "Failed to open CA cert"))?
         .read_to_end(&mut buf)
         .map_err(|_| error!(RuntimeError, "Failed to read CA cert"))?;
-    reqwest::Certificate::from_pem(&buf)
-        .map_err(|_| error!(RuntimeError, "Invalid CA cert"))
+    let certs = reqwest::Certificate::from_pem_bundle(&buf)
+        .map_err(|_| error!(RuntimeError, "Invalid CA cert"))?;
+    if certs.is_empty()
+    {
+        return Err(rterr!("No cert found in {}", filename));
+    }
+    Ok(certs)
+}
+
+/// Read the CA certs at `path`, which is either a cert file or a
+/// directory. In a directory, all the `.pem` and `.crt` files are
+/// read, and other files are ignored.
+fn readCerts(path: &str) -> Result<Vec<reqwest::Certificate>, Error>
+{
+    let p = std::path::Path::new(path);
+    if !p.is_dir()
+    {
+        return readCert(path);
+    }
+
+    let mut files: Vec<std::path::PathBuf> = std::fs::read_dir(p)
+        .map_err(|e| rterr!("Failed to read CA cert directory {}: {}",
+                            path, e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|f| f.is_file() && f.extension().is_some_and(
+            |ext| ext == "pem" || ext == "crt"))
+        .collect();
+    files.sort();
+    let mut certs = Vec::new();
+    for f in files
+    {
+        certs.append(&mut readCert(&f.to_string_lossy())?);
+    }
+    Ok(certs)
 }
 
 pub enum HealthStatus
@@ -33,27 +146,157 @@ pub enum HealthStatus
     Performance,
     Uninitialized,
     Sealed,
+    /// A status code not documented for the health endpoint, like
+    /// during a seal migration.
+    Unknown(u16),
 }
 
+#[derive(PartialEq)]
 pub enum KeyOrDir
 {
     Key(String), Dir(String),
 }
 
+impl KeyOrDir
+{
+    pub fn name(&self) -> &str
+    {
+        match self
+        {
+            KeyOrDir::Key(name) | KeyOrDir::Dir(name) => name,
+        }
+    }
+}
+
+/// What kind of items to search for.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SearchTarget
+{
+    Key, Dir, Both,
+}
+
+impl FromStr for SearchTarget
+{
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s
+        {
+            "key" => Ok(SearchTarget::Key),
+            "dir" => Ok(SearchTarget::Dir),
+            "both" => Ok(SearchTarget::Both),
+            _ => Err(rterr!("Invalid search type: {}", s)),
+        }
+    }
+}
+
+/// How search patterns are matched.
+pub enum Matcher
+{
+    /// Case-insensitive substring of the name. The pattern is
+    /// lowercase.
+    Substring(String),
+    /// Regular expression on the full path.
+    Regex(regex::Regex),
+    /// Shell glob on the full path.
+    Glob(glob::Pattern),
+}
+
+/// A `*` or `?` in a glob does not match a `/`, like in the shell.
+/// Use `**` to match across directories.
+const GLOB_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+impl Matcher
+{
+    pub fn substring(pattern: &str) -> Self
+    {
+        Matcher::Substring(pattern.to_lowercase())
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self, Error>
+    {
+        regex::Regex::new(pattern).map(Matcher::Regex)
+            .map_err(|e| rterr!("Invalid regex: {}", e))
+    }
+
+    pub fn glob(pattern: &str) -> Result<Self, Error>
+    {
+        glob::Pattern::new(pattern).map(Matcher::Glob)
+            .map_err(|e| rterr!("Invalid glob: {}", e))
+    }
+
+    /// Whether the item at `path` matches. `name` is the last
+    /// component of the path.
+    pub fn matches(&self, name: &str, path: &Path) -> bool
+    {
+        match self
+        {
+            Matcher::Substring(s) => name.to_lowercase().contains(s.as_str()),
+            Matcher::Regex(r) => r.is_match(&path.to_string()),
+            Matcher::Glob(g) => g.matches_with(&path.to_string(),
+                                               GLOB_OPTIONS),
+        }
+    }
+
+    /// Whether the value of a field matches.
+    pub fn matchesValue(&self, value: &str) -> bool
+    {
+        match self
+        {
+            Matcher::Substring(s) => value.to_lowercase().contains(s.as_str()),
+            Matcher::Regex(r) => r.is_match(value),
+            Matcher::Glob(g) => g.matches_with(value, GLOB_OPTIONS),
+        }
+    }
+}
+
+impl fmt::Display for Matcher
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Matcher::Substring(s) => write!(f, "{}", s),
+            Matcher::Regex(r) => write!(f, "{}", r),
+            Matcher::Glob(g) => write!(f, "{}", g),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SearchResult
+{
+    /// Matching keys
+    pub keys: Vec<Path>,
+    /// Matching directories
+    pub dirs: Vec<Path>,
+}
+
+impl SearchResult
+{
+    pub fn is_empty(&self) -> bool
+    {
+        self.keys.is_empty() && self.dirs.is_empty()
+    }
+}
+
 impl HealthStatus
 {
-    pub fn fromHTTPStatus(status: u16) -> Result<Self, Error>
+    pub fn fromHTTPStatus(status: u16) -> Self
     {
         match status
         {
-            200 => Ok(HealthStatus::Active),
-            429 => Ok(HealthStatus::Standby),
-            472 => Ok(HealthStatus::Recovery),
-            473 => Ok(HealthStatus::Performance),
-            501 => Ok(HealthStatus::Uninitialized),
-            503 => Ok(HealthStatus::Sealed),
-            _ => Err(error!(VaultError, "Invalid status code from health: {}",
-                       status)),
+            200 => HealthStatus::Active,
+            429 => HealthStatus::Standby,
+            472 => HealthStatus::Recovery,
+            473 => HealthStatus::Performance,
+            501 => HealthStatus::Uninitialized,
+            503 => HealthStatus::Sealed,
+            _ => HealthStatus::Unknown(status),
         }
     }
 }
@@ -62,20 +305,67 @@ impl fmt::Display for HealthStatus
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
-        write!(f, "{}",
-               match self
-               {
-                   HealthStatus::Active => "active",
-                   HealthStatus::Standby => "standby",
-                   HealthStatus::Recovery => "recovery",
-                   HealthStatus::Performance => "performance",
-                   HealthStatus::Uninitialized => "uninitialized",
-                   HealthStatus::Sealed => "sealed",
-               })
+        let name = match self
+        {
+            HealthStatus::Active => "active",
+            HealthStatus::Standby => "standby",
+            HealthStatus::Recovery => "recovery",
+            HealthStatus::Performance => "performance",
+            HealthStatus::Uninitialized => "uninitialized",
+            HealthStatus::Sealed => "sealed",
+            HealthStatus::Unknown(code) =>
+                return write!(f, "unknown({})", code),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Metadata of one version of a KV v2 entry.
+#[derive(Deserialize)]
+pub struct VersionMetadata
+{
+    pub created_time: String,
+    /// Empty if not deleted.
+    #[serde(default)]
+    pub deletion_time: String,
+    #[serde(default)]
+    pub destroyed: bool,
+}
+
+/// Metadata of a KV v2 entry.
+#[derive(Deserialize)]
+pub struct Metadata
+{
+    pub current_version: u64,
+    pub created_time: String,
+    pub updated_time: String,
+    /// Keys are version numbers.
+    #[serde(default)]
+    pub versions: HashMap<String, VersionMetadata>,
+}
+
+/// Configuration of the KV v2 mount.
+#[derive(Deserialize)]
+pub struct MountConfig
+{
+    /// Number of versions kept for each entry. Zero means the Vault
+    /// default, which is 10.
+    pub max_versions: u64,
+    /// Whether writes must specify the current version.
+    #[serde(default)]
+    pub cas_required: bool,
+}
+
+impl Metadata
+{
+    /// The metadata of the current version, if available.
+    pub fn current(&self) -> Option<&VersionMetadata>
+    {
+        self.versions.get(&self.current_version.to_string())
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Path
 {
     components: Vec<String>,
@@ -99,6 +389,12 @@ impl Path
         p.push(comp);
         p
     }
+
+    /// Number of components in the path.
+    pub fn depth(&self) -> usize
+    {
+        self.components.len()
+    }
 }
 
 impl fmt::Display for Path
@@ -110,38 +406,125 @@ impl fmt::Display for Path
 }
 
 
+/// The warnings in a Vault response.
+fn responseWarnings(res: &serde_json::Value) -> Vec<&str>
+{
+    res["warnings"].as_array().into_iter().flatten()
+        .filter_map(|w| w.as_str()).collect()
+}
+
+/// The error for a request refused by Vault with `status`. A
+/// refusal is an AuthError whichever request it was, so that the
+/// exit code does not depend on the endpoint.
+fn requestError(status: reqwest::StatusCode, msg: String) -> Error
+{
+    if status == reqwest::StatusCode::FORBIDDEN
+    {
+        Error::AuthError(msg)
+    }
+    else
+    {
+        Error::VaultError(msg)
+    }
+}
+
+/// The username in the metadata of a token, from a token lookup.
+fn tokenUsername(info: &serde_json::Value) -> Option<String>
+{
+    info["data"]["meta"]["username"].as_str().map(|n| n.to_lowercase())
+}
+
 pub struct Client<'a>
 {
     end_point: String,
-    token: Option<String>,
+    token: Mutex<Option<String>>,
+    /// False if the token is from the cache and was not checked,
+    /// because of `prefer_cache`.
+    token_checked: AtomicBool,
+    /// Held while checking the token or logging in again after a
+    /// request is refused.
+    relogin: tokio::sync::Mutex<()>,
+    /// Username from the token, if `username_from_token` is set.
+    username: Option<String>,
     config: &'a config::Config,
-    client: reqwest::Client,
+    client: reqwest_middleware::ClientWithMiddleware,
+    /// Limits the number of requests in flight.
+    permits: Semaphore,
 }
 
 impl<'a> Client<'a>
 {
     pub fn new(conf: &'a config::Config) -> Result<Self, Error>
     {
+        let mut certs = Vec::new();
+        for cert_path in &conf.ca_certs
+        {
+            certs.append(&mut readCerts(cert_path)?);
+        }
         let mut builder = reqwest::Client::builder();
-        for cert_file in &conf.ca_certs
+        if conf.trust_system_roots
+        {
+            builder = builder.tls_certs_merge(certs);
+        }
+        else
         {
-            builder = builder.add_root_certificate(readCert(cert_file)?);
+            if certs.is_empty()
+            {
+                return Err(error!(ConfigError, "No CA cert to trust, because \
+                                  trust_system_roots is false and ca_certs \
+                                  is empty"));
+            }
+            builder = builder.tls_certs_only(certs);
+        }
+        if let Some(n) = conf.pool_max_idle_per_host
+        {
+            builder = builder.pool_max_idle_per_host(n);
+        }
+        if conf.follow_standby_redirect
+        {
+            // Redirects are followed by hand, because reqwest drops
+            // the token when redirecting to another host.
+            builder = builder.redirect(reqwest::redirect::Policy::none());
+        }
+        if conf.http2_prior_knowledge
+        {
+            builder = builder.http2_prior_knowledge();
         }
         let client = builder.build().map_err(
             |e| error!(RuntimeError, "Failed to build client: {}", e))?;
+        let mut client = reqwest_middleware::ClientBuilder::new(client);
+        if conf.trace
+        {
+            client = client.with(crate::trace::TraceMiddleware);
+        }
+        let client = client.build();
 
         Ok(Self {
             end_point: conf.end_point.clone(),
-            token: None,
+            token: Mutex::new(None),
+            token_checked: AtomicBool::new(true),
+            relogin: tokio::sync::Mutex::new(()),
+            username: None,
             config: conf,
-            client: client,
+            client,
+            permits: Semaphore::new(conf.concurrency.max(1)),
         })
     }
 
+    fn token(&self) -> Option<String>
+    {
+        self.token.lock().unwrap().clone()
+    }
+
+    fn setToken(&self, token: Option<String>)
+    {
+        *self.token.lock().unwrap() = token;
+    }
+
     fn buildReq(&self, method: reqwest::Method, url: &str) ->
-        reqwest::RequestBuilder
+        reqwest_middleware::RequestBuilder
     {
-        if let Some(token) = &self.token
+        if let Some(token) = self.token()
         {
             self.client.request(method, url).bearer_auth(token)
         }
@@ -151,202 +534,1353 @@ impl<'a> Client<'a>
         }
     }
 
-    #[allow(dead_code)]
-    pub async fn health(&self) -> Result<HealthStatus, Error>
+    /// Send a request, waiting first if too many requests are already
+    /// in flight. If the request is refused with a cached token that
+    /// was not checked, check the token, and resend the request after
+    /// logging in again if it is invalid.
+    async fn send(&self, req: reqwest_middleware::RequestBuilder) ->
+        reqwest_middleware::Result<reqwest::Response>
     {
-        let code = self.client.get(&format!("{}v1/sys/health", self.end_point))
-            .send().await
-            .map_err(|e| error!(HTTPError, "Failed to send request: {}", e))?
-            .status().as_u16();
-        HealthStatus::fromHTTPStatus(code)
+        let req = req.build()?;
+        let used = req.headers().get(reqwest::header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer ")).map(str::to_owned);
+        let retry = if self.token_checked.load(Ordering::SeqCst)
+        {
+            None
+        }
+        else
+        {
+            req.try_clone()
+        };
+        let res = self.sendOnce(req).await?;
+        if res.status() != reqwest::StatusCode::FORBIDDEN
+        {
+            return Ok(res);
+        }
+        let (mut retry, used) = match (retry, used)
+        {
+            (Some(r), Some(u)) => (r, u),
+            _ => return Ok(res),
+        };
+        if !Box::pin(self.refreshToken(&used)).await
+        {
+            return Ok(res);
+        }
+        if let Some(token) = self.token()
+        {
+            let value = format!("Bearer {}", token).parse()
+                .expect("Token is a valid header value");
+            retry.headers_mut().insert(reqwest::header::AUTHORIZATION, value);
+        }
+        self.sendOnce(retry).await
     }
 
-    /// Revoke the token if exists, and clear runtime info.
-    #[allow(dead_code)]
-    pub async fn logout(&mut self) -> Result<(), Error>
+    async fn sendOnce(&self, req: reqwest::Request) ->
+        reqwest_middleware::Result<reqwest::Response>
     {
-        if self.token.is_none() { return Ok(()); }
-
-        let res = self.buildReq(reqwest::Method::POST,
-                                &format!("{}v1/auth/token/revoke-self",
-                                         self.end_point))
-            .send().await.map_err(
-                |e| error!(HTTPError, "Failed to send logout request: {}", e))?;
-        if res.status().as_u16() == 403
+        let permit = self.permits.acquire().await
+            .expect("Request semaphore is never closed");
+        if self.config.follow_standby_redirect
         {
-            eprintln!("Invalid token. Maybe it has expired. Clearing token cache...");
+            self.sendToActive(req, &permit).await
         }
         else
         {
-            res.error_for_status().map_err(
-                |e| error!(VaultError, "Failed to logout: {}", e))?;
+            self.client.execute(req).await
         }
-        self.token = None;
-        setRuntimeInfo("token", None, self.config)
     }
 
-    /// Login using a username and a password. Acquire and cache a new
+    /// After a request with the token `used` is refused, check the
+    /// token if it was not checked, and login again if it is invalid.
+    /// Return whether the request should be resent with the current
     /// token.
-    async fn loginNew(&mut self, password: &str) -> Result<(), Error>
+    async fn refreshToken(&self, used: &str) -> bool
     {
-        let res: serde_json::Value =
-            self.client.post(&format!("{}v1/auth/userpass/login/{}",
-                                      self.end_point, self.config.username()))
-            .json(&json!({"password": password, "token_max_ttl": 3600 * 24}))
-            .send().await.map_err(
-                |e| error!(HTTPError, "Failed to send login request: {}", e))?
-            .json().await.map_err(
-                |_| rterr!("Failed to parse JSON"))?;
-        if let Some(msg) = res["errors"][0].as_str()
+        let _guard = self.relogin.lock().await;
+        if self.token().as_deref() != Some(used)
+        {
+            // Another request already logged in again.
+            return true;
+        }
+        if self.token_checked.swap(true, Ordering::SeqCst) ||
+            self.lookupToken().await.is_ok()
+        {
+            // The token is valid, so the refusal is real.
+            return false;
+        }
+        info!(self.config, "Token is invalid. Logging in again...");
+        match self.loginFresh().await
         {
-            return Err(error!(VaultError, "Failed to login: {}", msg));
+            Ok(()) => true,
+            Err(e) =>
+            {
+                info!(self.config, "{}", e.message());
+                false
+            },
         }
-        self.token = res["auth"]["client_token"].as_str().map(|t| t.to_owned());
-        setRuntimeInfo("token", Some(&self.token.as_ref().unwrap()),
-                       self.config)?;
+    }
 
-        Ok(())
+    /// Like `send`, but always talk to `end_point`, even if it is a
+    /// standby.
+    async fn sendHere(&self, req: reqwest_middleware::RequestBuilder) ->
+        reqwest_middleware::Result<reqwest::Response>
+    {
+        let _permit = self.permits.acquire().await
+            .expect("Request semaphore is never closed");
+        req.send().await
     }
 
-    pub async fn lookupToken(&self) -> Result<serde_json::Value, Error>
+    /// Send a request, and resend it to the active node if it is
+    /// redirected or refused by a standby. All the requests for this
+    /// are sent under `permit`, one after another, so they count as
+    /// one against the concurrency limit.
+    async fn sendToActive(&self, req: reqwest::Request,
+                          permit: &SemaphorePermit<'_>) ->
+        reqwest_middleware::Result<reqwest::Response>
     {
-        let res: serde_json::Value =
-            self.buildReq(reqwest::Method::GET, &format!(
-                "{}/v1/auth/token/lookup-self",self.end_point))
-            .send().await.map_err(
-                |e| error!(HTTPError,
-                           "Failed to send token lookup request: {}", e))?
-            .json().await.map_err(
-                |_| rterr!("Failed to parse JSON"))?;
-        if let Some(msg) = res["errors"][0].as_str()
+        let retry = req.try_clone();
+        let res = self.client.execute(req).await?;
+        let status = res.status();
+        if !status.is_redirection() &&
+            status != reqwest::StatusCode::TOO_MANY_REQUESTS
         {
-            return Err(error!(VaultError, "Failed to lookup token: {}", msg));
+            return Ok(res);
         }
-        Ok(res)
+        let mut retry = if let Some(r) = retry
+        {
+            r
+        }
+        else
+        {
+            return Ok(res);
+        };
+        let location = res.headers().get(reqwest::header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|l| reqwest::Url::parse(l).ok());
+        if let Some(url) = location
+        {
+            *retry.url_mut() = url;
+        }
+        else if let Some(leader) = self.leaderAddress(permit).await
+        {
+            // A standby that does not redirect. Send the same path to
+            // the active node instead.
+            let url = retry.url_mut();
+            if url.set_scheme(leader.scheme()).is_err() ||
+                url.set_host(leader.host_str()).is_err() ||
+                url.set_port(leader.port()).is_err()
+            {
+                return Ok(res);
+            }
+        }
+        else
+        {
+            return Ok(res);
+        }
+        self.client.execute(retry).await
     }
 
-    pub fn loginUsingCachedToken(&mut self) -> Result<(), Error>
+    /// Address of the active node of the cluster, if known. This is
+    /// only asked while holding a request `permit`.
+    async fn leaderAddress(&self, _permit: &SemaphorePermit<'_>) ->
+        Option<reqwest::Url>
     {
-        self.token = getRuntimeInfo("token", self.config)?;
-        Ok(())
+        let res: serde_json::Value = self.client.get(
+            format!("{}v1/sys/leader", self.end_point)).send().await.ok()?
+            .jsonLimited(self.config.max_response_bytes).await.ok()?;
+        reqwest::Url::parse(res["leader_address"].as_str()?).ok()
     }
 
-    pub async fn loginPromptPassword(&mut self) -> Result<(), Error>
+    /// The API path (without the `v1/` prefix) of the entry at `path`
+    /// under a KV v2 endpoint, e.g. `data` or `metadata`.
+    fn enginePath(&self, endpoint: &str, path: &str) -> String
     {
-        let pass = rpassword::prompt_password("Password: ")
-            .map_err(|_| rterr!("Failed to read password"))?;
-        self.loginNew(&pass).await
+        format!("{}/{}/{}/{}", self.config.mount(), endpoint, self.username(),
+                path)
     }
 
-    pub async fn login(&mut self) -> Result<(), Error>
+    /// The username in entry paths.
+    fn username(&self) -> String
+    {
+        self.username.clone().unwrap_or_else(|| self.config.username())
+    }
+
+    /// The API path of the entry at `path` in KV v1, which has no
+    /// endpoint segment.
+    fn kv1Path(&self, path: &str) -> String
     {
-        if self.loginUsingCachedToken().is_ok()
+        format!("{}/{}/{}", self.config.mount(), self.username(), path)
+    }
+
+    fn dataPath(&self, path: &str) -> String
+    {
+        if self.config.kv_version == 1
         {
-            return Ok(());
+            return self.kv1Path(path);
         }
-        if self.lookupToken().await.is_ok()
+        self.enginePath(&self.config.data_prefix, path)
+    }
+
+    fn metadataPath(&self, path: &str) -> String
+    {
+        if self.config.kv_version == 1
         {
-            Ok(())
+            return self.kv1Path(path);
         }
-        else
+        self.enginePath(&self.config.metadata_prefix, path)
+    }
+
+    /// Print the warnings in a Vault response to stderr, like a
+    /// deprecated endpoint or a capped TTL.
+    fn printWarnings(&self, res: &serde_json::Value)
+    {
+        for warning in responseWarnings(res)
         {
-            self.loginPromptPassword().await
+            info!(self.config, "Vault warning: {}", warning);
         }
     }
 
-    pub async fn list(&self, path: &str) -> Result<Vec<KeyOrDir>, Error>
+    /// Fail unless writing is allowed. `action` describes the write in
+    /// the error message.
+    pub fn checkWritable(&self, action: &str) -> Result<(), Error>
     {
-        let abs_path = &format!("{}/v1/passwords/metadata/{}/{}",
-                                self.end_point, self.config.username(), path);
-        let res: serde_json::Value = self.buildReq(
-            reqwest::Method::from_str("LIST").unwrap(), abs_path)
-            .send().await.map_err(
-                |e| error!(HTTPError, "Failed to send login request: {}", e))?
-            .json().await.map_err(
-                |_| rterr!("Failed to parse JSON"))?;
-        if let Some(msg) = res["errors"][0].as_str()
+        if self.config.read_only
         {
-            return Err(error!(VaultError, "Failed to list {}: {}",
-                              abs_path, msg));
+            return Err(rterr!("Refusing to {} in read-only mode", action));
         }
+        Ok(())
+    }
 
-        // Each item listed could be a key or a directory. As far as I
-        // know the only way to tell is to see if there a tailing
-        // slash.
-        res["data"]["keys"].as_array().ok_or_else(
-                || rterr!("List result is not a list"))?
-            .iter().map(|v: &serde_json::Value| {
-                let item = v.as_str().ok_or_else(
-                    || rterr!("List item is not a string"))?;
-                if item.ends_with('/')
-                {
-                    Ok(KeyOrDir::Dir(item[..item.len()-1].to_owned()))
-                }
-                else
-                {
-                    Ok(KeyOrDir::Key(item.to_owned()))
-                }
-            }).collect()
+    /// Send a request that does not return any data, which is the
+    /// case for most write operations. `action` describes the request
+    /// in error messages. All write operations should go through
+    /// this, so that read-only mode cannot be bypassed.
+    async fn sendWrite(&self, method: reqwest::Method, api_path: &str,
+                       body: Option<serde_json::Value>, action: &str) ->
+        Result<(), Error>
+    {
+        self.checkWritable(action)?;
+        let mut req = self.buildReq(method, &format!("{}v1/{}",
+                                                     self.end_point, api_path));
+        if let Some(b) = &body
+        {
+            req = req.json(b);
+        }
+        let res = self.send(req).await.map_err(
+            |e| error!(HTTPError, "Failed to send {} request: {}", action, e))?;
+        let status = res.status();
+        // Successful writes often have no body.
+        let res: serde_json::Value =
+            res.jsonLimited(self.config.max_response_bytes).await
+            .unwrap_or_default();
+        self.printWarnings(&res);
+        if status.is_success()
+        {
+            return Ok(());
+        }
+        let msg = res["errors"][0].as_str().map(|m| m.to_owned())
+            .unwrap_or_else(|| status.to_string());
+        if status == reqwest::StatusCode::NOT_FOUND
+        {
+            return Err(error!(NotFoundError, "Failed to {}: {}", action, msg));
+        }
+        Err(requestError(status, format!("Failed to {}: {}", action, msg)))
     }
 
-    /// Retrieve the key-value paired stored at `path`.
-    pub async fn get(&self, path: &str) -> Result<StringMap, Error>
+    pub async fn health(&self) -> Result<HealthStatus, Error>
     {
-        let mut res: serde_json::Value =
-            self.buildReq(reqwest::Method::GET, &format!(
-                "{}/v1/passwords/data/{}/{}", self.end_point,
-                self.config.username(), path))
-            .send().await.map_err(
-                |e| error!(HTTPError, "Failed to send get request: {}", e))?
-            .json().await.map_err(
-                |_| rterr!("Failed to parse JSON"))?;
-        let result: StringMap = serde_json::from_value(
-            res["data"]["data"].take())
-            .map_err(|_| rterr!("Get result is not a dict"))?;
-        Ok(result)
+        let code = self.sendHere(self.client.get(
+            format!("{}v1/sys/health", self.end_point))).await
+            .map_err(|e| error!(HTTPError, "Failed to send request: {}", e))?
+            .status().as_u16();
+        Ok(HealthStatus::fromHTTPStatus(code))
+    }
+
+    /// The full body of the health endpoint. Vault returns a body
+    /// with all the status codes of `HealthStatus`, not only when it
+    /// is active.
+    pub async fn healthDetails(&self) -> Result<serde_json::Value, Error>
+    {
+        self.sendHere(self.client.get(
+            format!("{}v1/sys/health", self.end_point))).await
+            .map_err(|e| error!(HTTPError, "Failed to send request: {}", e))?
+            .jsonLimited(self.config.max_response_bytes).await
     }
 
-    /// Recursively search though all entries in the engine, for all keys
-    /// that contains `snippet`. Return a vector of key paths.
-    pub async fn search(&self, snippet: &str) -> Result<Vec<Path>, Error>
+    /// Revoke the token if exists, and clear runtime info. The cache
+    /// is cleared even if the revocation fails. Batch tokens cannot
+    /// be revoked, so they are only forgotten.
+    #[allow(dead_code)]
+    pub async fn logout(&mut self) -> Result<(), Error>
     {
-        let mut result: Vec<Path> = vec![];
-        let mut to_search: Vec<Path> = vec![Path::new(),];
+        if self.token().is_none() { return Ok(()); }
 
-        // Breath-first search through all entries.
-        while !to_search.is_empty()
+        let batch = self.lookupToken().await
+            .is_ok_and(|info| info["data"]["type"] == "batch");
+        let result = if batch
         {
-            let mut next_to_search: Vec<Path> = Vec::default();
-            for path in &to_search
+            info!(self.config, "A batch token cannot be revoked. It stays \
+                                valid until it expires.");
+            Ok(())
+        }
+        else
+        {
+            self.revokeToken().await
+        };
+        self.setToken(None);
+        setRuntimeInfo("username", None, self.config)?;
+        setRuntimeInfo("token", None, self.config)?;
+        result
+    }
+
+    async fn revokeToken(&self) -> Result<(), Error>
+    {
+        let res = self.send(self.buildReq(
+            reqwest::Method::POST,
+            &format!("{}v1/auth/token/revoke-self", self.end_point)))
+            .await.map_err(
+                |e| error!(HTTPError, "Failed to send logout request: {}", e))?;
+        if res.status().as_u16() == 403
+        {
+            if !self.config.quiet
+            {
+                eprintln!("Invalid token. Maybe it has expired. \
+                           Clearing token cache...");
+            }
+            return Ok(());
+        }
+        res.error_for_status().map_err(
+            |e| error!(VaultError, "Failed to logout: {}", e))?;
+        Ok(())
+    }
+
+    /// Send a login request to `api_path` and cache the token in the
+    /// response. This is shared by all the auth methods.
+    async fn completeLogin(&self, api_path: &str,
+                           mut body: serde_json::Value) -> Result<(), Error>
+    {
+        if let Some(t) = &self.config.token_type
+        {
+            body["token_type"] = json!(t);
+        }
+        let req = self.client.post(
+            format!("{}v1/{}", self.end_point, api_path)).json(&body);
+        self.sendLogin(req).await
+    }
+
+    /// Send the login request `req` and cache the token in the
+    /// response.
+    async fn sendLogin(&self, req: reqwest_middleware::RequestBuilder) ->
+        Result<(), Error>
+    {
+        let spinner = Spinner::start("Logging in...", self.config);
+        let res: serde_json::Value = self.send(req).await.map_err(
+                |e| error!(HTTPError, "Failed to send login request: {}", e))?
+            .jsonLimited(self.config.max_response_bytes).await?;
+        drop(spinner);
+        self.printWarnings(&res);
+        if let Some(msg) = res["errors"][0].as_str()
+        {
+            return Err(error!(AuthError, "Failed to login: {}", msg));
+        }
+        let token = res["auth"]["client_token"].as_str().ok_or_else(
+            || error!(AuthError, "No token in login response"))?;
+        self.setToken(Some(token.to_owned()));
+        setRuntimeInfo("token", Some(token), self.config)?;
+        // The cached username belongs to the old token.
+        setRuntimeInfo("username", None, self.config)?;
+        Ok(())
+    }
+
+    /// Login using a username and a password at the default mount of
+    /// `auth_method`, `userpass` or `ldap`. Acquire and cache a new
+    /// token.
+    async fn loginNew(&self, password: &str) -> Result<(), Error>
+    {
+        let path = format!("auth/{}/login/{}", self.config.auth_method,
+                           self.config.username());
+        self.completeLogin(&path, json!({"password": password,
+                                         "token_max_ttl": 3600 * 24})).await
+    }
+
+    /// Login with a JWT obtained elsewhere, e.g. from an OIDC
+    /// provider. Acquire and cache a new token.
+    async fn loginJWT(&self, jwt: &str) -> Result<(), Error>
+    {
+        let mut body = json!({"jwt": jwt});
+        if let Some(role) = &self.config.jwt_role
+        {
+            body["role"] = json!(role);
+        }
+        self.completeLogin("auth/jwt/login", body).await
+    }
+
+    /// Login with the configured AppRole. Acquire and cache a new
+    /// token.
+    async fn loginAppRole(&self, role_id: &str) -> Result<(), Error>
+    {
+        let mut body = json!({"role_id": role_id});
+        if let Some(secret_id) = self.config.approleSecretID()?
+        {
+            body["secret_id"] = json!(secret_id);
+        }
+        self.completeLogin("auth/approle/login", body).await
+    }
+
+    /// Login in a browser with the OIDC provider configured in Vault.
+    /// The provider redirects the browser to a local server with an
+    /// authorization code, which is exchanged for a token.
+    async fn loginOIDCBrowser(&self) -> Result<(), Error>
+    {
+        let mut nonce_bytes = [0u8; 16];
+        getrandom::fill(&mut nonce_bytes).map_err(
+            |e| rterr!("Failed to get random bytes: {}", e))?;
+        let nonce: String = nonce_bytes.iter().map(|b| format!("{:02x}", b))
+            .collect();
+        let mut body = json!({"redirect_uri": oidc::redirectURI(self.config),
+                              "client_nonce": nonce});
+        if let Some(role) = &self.config.jwt_role
+        {
+            body["role"] = json!(role);
+        }
+        let res: serde_json::Value = self.send(self.client.post(format!(
+            "{}v1/auth/oidc/oidc/auth_url", self.end_point)).json(&body))
+            .await.map_err(
+                |e| error!(HTTPError, "Failed to send OIDC request: {}", e))?
+            .jsonLimited(self.config.max_response_bytes).await?;
+        self.printWarnings(&res);
+        if let Some(msg) = res["errors"][0].as_str()
+        {
+            return Err(error!(AuthError, "Failed to start OIDC login: {}",
+                              msg));
+        }
+        // Vault returns an empty URL if the redirect URI is not
+        // allowed.
+        let url = res["data"]["auth_url"].as_str().filter(|u| !u.is_empty())
+            .ok_or_else(|| error!(AuthError, "No OIDC auth URL. Check that \
+                                              {} is an allowed redirect URI",
+                                  oidc::redirectURI(self.config)))?;
+
+        let listener = oidc::listen(self.config).await?;
+        eprintln!("Complete the login in the browser. If it does not open, \
+                   go to\n{}", url);
+        oidc::openBrowser(url);
+        let (code, state) = oidc::waitForCallback(&listener, self.config)
+            .await?;
+        let url = reqwest::Url::parse_with_params(
+            &format!("{}v1/auth/oidc/oidc/callback", self.end_point),
+            [("code", code), ("state", state), ("client_nonce", nonce)])
+            .map_err(|e| rterr!("Invalid OIDC callback URL: {}", e))?;
+        self.sendLogin(self.client.get(url)).await
+    }
+
+    pub async fn lookupToken(&self) -> Result<serde_json::Value, Error>
+    {
+        let res: serde_json::Value =
+            self.send(self.buildReq(reqwest::Method::GET, &format!(
+                "{}v1/auth/token/lookup-self",self.end_point)))
+            .await.map_err(
+                |e| error!(HTTPError,
+                           "Failed to send token lookup request: {}", e))?
+            .jsonLimited(self.config.max_response_bytes).await?;
+        self.printWarnings(&res);
+        if let Some(msg) = res["errors"][0].as_str()
+        {
+            return Err(error!(AuthError, "Failed to lookup token: {}", msg));
+        }
+        Ok(res)
+    }
+
+    pub fn loginUsingCachedToken(&self) -> Result<(), Error>
+    {
+        self.setToken(getRuntimeInfo("token", self.config)?);
+        if self.token().is_none()
+        {
+            return Err(error!(AuthError, "No cached token"));
+        }
+        Ok(())
+    }
+
+    pub async fn loginPromptPassword(&self) -> Result<(), Error>
+    {
+        let pass = if let Some(path) = &self.config.askpass
+        {
+            readAskpass(path)?
+        }
+        else
+        {
+            rpassword::prompt_password("Password: ")
+                .map_err(|_| rterr!("Failed to read password"))?
+        };
+        self.loginNew(&pass).await
+    }
+
+    /// Login with the configured auth method without a cached token.
+    async fn loginFresh(&self) -> Result<(), Error>
+    {
+        if let Some(role_id) = &self.config.approle_role_id
+        {
+            return self.loginAppRole(role_id).await;
+        }
+        match self.config.auth_method.as_str()
+        {
+            "userpass" | "ldap" => self.loginPromptPassword().await,
+            "jwt" | "oidc" =>
+            {
+                let jwt = self.config.jwt()?;
+                self.loginJWT(&jwt).await
+            },
+            "oidc_browser" => self.loginOIDCBrowser().await,
+            m => Err(error!(ConfigError, "Invalid auth_method: {}", m)),
+        }
+    }
+
+    /// Login with the token from `--token`, or in `VAULT_TOKEN`, e.g.
+    /// from `vault login` in the shell. The token is not cached,
+    /// because it belongs to the shell. It is checked first, unless
+    /// `prefer_cache` is set and the username is not taken from the
+    /// token; then, like a cached token, it is only checked when a
+    /// request is refused. Return whether the token is used.
+    async fn loginFromEnv(&mut self) -> bool
+    {
+        let (token, source) = if let Some(t) = &self.config.token
+        {
+            (t.clone(), "--token")
+        }
+        else
+        {
+            match std::env::var("VAULT_TOKEN")
+            {
+                Ok(t) if !t.is_empty() => (t, "VAULT_TOKEN"),
+                _ => return false,
+            }
+        };
+        self.setToken(Some(token));
+        if self.config.prefer_cache && !self.config.username_from_token
+        {
+            self.token_checked.store(false, Ordering::SeqCst);
+            return true;
+        }
+        match self.lookupToken().await
+        {
+            Ok(info) =>
+            {
+                // The cached username may belong to another token.
+                if self.config.username_from_token
+                {
+                    if let Some(name) = tokenUsername(&info)
+                    {
+                        self.username = Some(name);
+                    }
+                }
+                true
+            },
+            Err(e) =>
+            {
+                info!(self.config, "Ignoring {}: {}", source, e.message());
+                self.setToken(None);
+                false
+            },
+        }
+    }
+
+    pub async fn login(&mut self) -> Result<(), Error>
+    {
+        if self.loginFromEnv().await
+        {
+            return Ok(());
+        }
+        let need_login = if self.loginUsingCachedToken().is_err()
+        {
+            true
+        }
+        else if self.config.prefer_cache
+        {
+            self.token_checked.store(false, Ordering::SeqCst);
+            false
+        }
+        else
+        {
+            let _spinner = Spinner::start("Checking token...", self.config);
+            self.lookupToken().await.is_err()
+        };
+        if need_login
+        {
+            self.loginFresh().await?;
+        }
+        self.resolveUsername().await
+    }
+
+    /// If `username_from_token` is set, take the username in entry
+    /// paths from the metadata of the token, and cache it. Keep the
+    /// username in the config if the token does not have one.
+    async fn resolveUsername(&mut self) -> Result<(), Error>
+    {
+        if !self.config.username_from_token
+        {
+            return Ok(());
+        }
+        if let Ok(Some(name)) = getRuntimeInfo("username", self.config)
+        {
+            self.username = Some(name);
+            return Ok(());
+        }
+        let info = self.lookupToken().await?;
+        if let Some(name) = tokenUsername(&info)
+        {
+            setRuntimeInfo("username", Some(&name), self.config)?;
+            self.username = Some(name);
+        }
+        Ok(())
+    }
+
+    pub async fn list(&self, path: &str) -> Result<Vec<KeyOrDir>, Error>
+    {
+        let abs_path = &format!("{}v1/{}", self.end_point,
+                                self.metadataPath(path));
+        let res = self.send(self.buildReq(
+            reqwest::Method::from_str("LIST").unwrap(), abs_path))
+            .await.map_err(
+                |e| error!(HTTPError, "Failed to send list request: {}", e))?;
+        let status = res.status();
+        // Vault says an empty directory is not found.
+        if status == reqwest::StatusCode::NOT_FOUND
+        {
+            return Ok(Vec::new());
+        }
+        let res: serde_json::Value =
+            res.jsonLimited(self.config.max_response_bytes).await?;
+        self.printWarnings(&res);
+        if let Some(msg) = res["errors"][0].as_str()
+        {
+            return Err(requestError(status, format!("Failed to list {}: {}",
+                                                    abs_path, msg)));
+        }
+
+        // Each item listed could be a key or a directory. As far as I
+        // know the only way to tell is to see if there a tailing
+        // slash.
+        res["data"]["keys"].as_array().ok_or_else(
+                || rterr!("List result is not a list"))?
+            .iter().map(|v: &serde_json::Value| {
+                let item = v.as_str().ok_or_else(
+                    || rterr!("List item is not a string"))?;
+                if let Some(dir) = item.strip_suffix('/')
+                {
+                    Ok(KeyOrDir::Dir(dir.to_owned()))
+                }
+                else
+                {
+                    Ok(KeyOrDir::Key(item.to_owned()))
+                }
+            }).collect()
+    }
+
+    /// Retrieve the entry at `path`, with all values as strings.
+    pub async fn get(&self, path: &str) -> Result<StringMap, Error>
+    {
+        Ok(stringifyEntry(self.getRaw(path).await?))
+    }
+
+    /// Retrieve the entry at `path` with the JSON values as they are
+    /// stored. Vault allows any JSON value in an entry, not only
+    /// strings. Only `client_encrypted_fields` are decrypted.
+    pub async fn getRaw(&self, path: &str) ->
+        Result<serde_json::Map<String, serde_json::Value>, Error>
+    {
+        Ok(self.getVersioned(path).await?.0)
+    }
+
+    /// Like `getRaw`, but also return the version that was read. A
+    /// check-and-set write of changes to the entry should use this
+    /// version, so that it fails if the entry was written in between.
+    /// There is no version in KV v1.
+    pub async fn getVersioned(&self, path: &str) ->
+        Result<(serde_json::Map<String, serde_json::Value>, Option<u64>),
+               Error>
+    {
+        let res = self.send(self.buildReq(reqwest::Method::GET, &format!(
+            "{}v1/{}", self.end_point, self.dataPath(path))))
+            .await.map_err(
+                |e| error!(HTTPError, "Failed to send get request: {}", e))?;
+        let status = res.status();
+        if status == reqwest::StatusCode::NOT_FOUND
+        {
+            return Err(error!(NotFoundError, "No entry at {}", path));
+        }
+        let mut res = res.jsonLimited(self.config.max_response_bytes)
+            .await?;
+        self.printWarnings(&res);
+        if let Some(msg) = res["errors"][0].as_str()
+        {
+            return Err(requestError(status, format!("Failed to get {}: {}",
+                                                    path, msg)));
+        }
+        // KV v2 wraps the entry with its metadata.
+        let (data, version) = if self.config.kv_version == 1
+        {
+            (res["data"].take(), None)
+        }
+        else
+        {
+            (res["data"]["data"].take(),
+             res["data"]["metadata"]["version"].as_u64())
+        };
+        if let serde_json::Value::Object(mut data) = data
+        {
+            crypt::decryptFields(&mut data, self.config)?;
+            Ok((data, version))
+        }
+        else
+        {
+            Err(rterr!("Get result is not a dict"))
+        }
+    }
+
+    /// Fail if the mount is KV v1, which does not have `what`.
+    fn requireV2(&self, what: &str) -> Result<(), Error>
+    {
+        if self.config.kv_version == 1
+        {
+            return Err(error!(VaultError, "{} requires KV v2, but kv_version \
+                                          is 1", what));
+        }
+        Ok(())
+    }
+
+    /// Retrieve the field structure of the entry at `path` from the KV
+    /// v2 `subkeys` endpoint, without the values. A field maps to
+    /// null, or to an object of its subkeys if its value is an
+    /// object.
+    pub async fn subkeys(&self, path: &str) ->
+        Result<serde_json::Map<String, serde_json::Value>, Error>
+    {
+        self.requireV2("Listing the fields with subkeys")?;
+        let res = self.send(self.buildReq(reqwest::Method::GET, &format!(
+            "{}v1/{}", self.end_point, self.enginePath("subkeys", path))))
+            .await.map_err(
+                |e| error!(HTTPError, "Failed to send subkeys request: {}",
+                           e))?;
+        let status = res.status();
+        if status == reqwest::StatusCode::NOT_FOUND
+        {
+            return Err(error!(NotFoundError, "No entry at {}", path));
+        }
+        let mut res = res.jsonLimited(self.config.max_response_bytes)
+            .await?;
+        self.printWarnings(&res);
+        if let Some(msg) = res["errors"][0].as_str()
+        {
+            return Err(requestError(status, format!(
+                "Failed to get subkeys of {}: {}", path, msg)));
+        }
+        if let serde_json::Value::Object(keys) = res["data"]["subkeys"].take()
+        {
+            Ok(keys)
+        }
+        else
+        {
+            Err(rterr!("Subkeys result is not a dict"))
+        }
+    }
+
+    /// Retrieve the KV v2 metadata of the entry at `path`.
+    pub async fn metadata(&self, path: &str) -> Result<Metadata, Error>
+    {
+        self.requireV2("Metadata")?;
+        let res = self.send(self.buildReq(reqwest::Method::GET, &format!(
+            "{}v1/{}", self.end_point, self.metadataPath(path))))
+            .await.map_err(
+                |e| error!(HTTPError, "Failed to send metadata request: {}",
+                           e))?;
+        let status = res.status();
+        if status == reqwest::StatusCode::NOT_FOUND
+        {
+            return Err(error!(NotFoundError, "No entry at {}", path));
+        }
+        let mut res = res.jsonLimited(self.config.max_response_bytes)
+            .await?;
+        self.printWarnings(&res);
+        if let Some(msg) = res["errors"][0].as_str()
+        {
+            return Err(requestError(status, format!(
+                "Failed to get metadata of {}: {}", path, msg)));
+        }
+        serde_json::from_value(res["data"].take())
+            .map_err(|e| rterr!("Invalid metadata: {}", e))
+    }
+
+    /// The current version of the entry at `path`, or None if there is
+    /// no entry. This is what check-and-set writes need.
+    pub async fn currentVersion(&self, path: &str) -> Result<Option<u64>, Error>
+    {
+        match self.metadata(path).await
+        {
+            Ok(meta) => Ok(Some(meta.current_version)),
+            Err(Error::NotFoundError(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Retrieve the configuration of the KV v2 mount.
+    pub async fn mountConfig(&self) -> Result<MountConfig, Error>
+    {
+        let res = self.send(self.buildReq(reqwest::Method::GET, &format!(
+            "{}v1/{}/config", self.end_point, self.config.mount())))
+            .await.map_err(
+                |e| error!(HTTPError, "Failed to send config request: {}", e))?;
+        let status = res.status();
+        let mut res: serde_json::Value =
+            res.jsonLimited(self.config.max_response_bytes).await?;
+        self.printWarnings(&res);
+        if let Some(msg) = res["errors"][0].as_str()
+        {
+            return Err(requestError(status, format!(
+                "Failed to get mount config: {}", msg)));
+        }
+        serde_json::from_value(res["data"].take())
+            .map_err(|e| rterr!("Invalid mount config: {}", e))
+    }
+
+    /// Write `data` as a new version of the entry at `path`. If `cas`
+    /// is given, the write succeeds only if the current version is
+    /// `cas`; 0 means the entry must not exist. This is required if
+    /// the mount has `cas_required` set. KV v1 has no versions, so
+    /// `cas` is ignored there.
+    pub async fn put(&self, path: &str,
+                     data: &serde_json::Map<String, serde_json::Value>,
+                     cas: Option<u64>) -> Result<(), Error>
+    {
+        let action = format!("write {}", path);
+        self.checkWritable(&action)?;
+        let data = &crypt::encryptFields(data, self.config)?;
+        let body = if self.config.kv_version == 1
+        {
+            json!(data)
+        }
+        else if let Some(version) = cas
+        {
+            json!({"data": data, "options": {"cas": version}})
+        }
+        else
+        {
+            json!({"data": data})
+        };
+        self.sendWrite(reqwest::Method::POST, &self.dataPath(path), Some(body),
+                       &action).await
+    }
+
+    /// Permanently delete the entry at `path` with all its versions.
+    pub async fn delete(&self, path: &str) -> Result<(), Error>
+    {
+        self.sendWrite(reqwest::Method::DELETE, &self.metadataPath(path), None,
+                       &format!("delete {}", path)).await
+    }
+
+    /// Soft-delete the given versions of the entry at `path`. The
+    /// versions can be recovered with undelete.
+    pub async fn deleteVersions(&self, path: &str, versions: &[u64]) ->
+        Result<(), Error>
+    {
+        self.requireV2("Deleting versions")?;
+        self.sendWrite(reqwest::Method::POST, &self.enginePath("delete", path),
+                       Some(json!({"versions": versions})),
+                       &format!("delete versions of {}", path)).await
+    }
+
+    /// Soft-delete the current version of the entry at `path`, keeping
+    /// the older ones. The version can be recovered with undelete.
+    pub async fn deleteLatest(&self, path: &str) -> Result<(), Error>
+    {
+        self.requireV2("Deleting the latest version")?;
+        self.sendWrite(reqwest::Method::DELETE, &self.dataPath(path), None,
+                       &format!("delete the latest version of {}", path))
+            .await
+    }
+
+    /// Recover the given soft-deleted versions of the entry at `path`.
+    pub async fn undeleteVersions(&self, path: &str, versions: &[u64]) ->
+        Result<(), Error>
+    {
+        self.requireV2("Undeleting versions")?;
+        self.sendWrite(reqwest::Method::POST,
+                       &self.enginePath("undelete", path),
+                       Some(json!({"versions": versions})),
+                       &format!("undelete versions of {}", path)).await
+    }
+
+    /// Visit every key and directory under `start`, breadth-first.
+    /// `visitor` is called with the full path of each item.
+    pub async fn walk<F>(&self, start: &Path, mut visitor: F) ->
+        Result<(), Error>
+        where F: FnMut(&Path, &KeyOrDir)
+    {
+        let mut to_search: Vec<Path> = vec![start.clone(),];
+        while !to_search.is_empty()
+        {
+            let mut next_to_search: Vec<Path> = Vec::default();
+            for path in &to_search
             {
                 for item in self.list(&path.to_string()).await?
                 {
-                    match item
+                    let item_path = path.pushed(item.name());
+                    visitor(&item_path, &item);
+                    if let KeyOrDir::Dir(_) = item
                     {
-                        KeyOrDir::Key(name) =>
-                        {
-                            if name.to_lowercase().find(&snippet).is_some()
-                            {
-                                result.push(path.pushed(&name));
-                            }
-                        },
-                        KeyOrDir::Dir(name) =>
-                        {
-                            next_to_search.push(path.pushed(&name));
-                        },
+                        next_to_search.push(item_path);
                     }
                 }
             }
             to_search = next_to_search;
         }
+        Ok(())
+    }
+
+    /// Recursively search though all entries in the engine, for all
+    /// items of kind `target` that match `matcher`.
+    pub async fn search(&self, matcher: &Matcher, target: SearchTarget) ->
+        Result<SearchResult, Error>
+    {
+        let mut result = SearchResult::default();
+        self.walk(&Path::new(), |path, item| match item
+        {
+            KeyOrDir::Key(name) =>
+            {
+                if target != SearchTarget::Dir && matcher.matches(name, path)
+                {
+                    result.keys.push(path.clone());
+                }
+            },
+            KeyOrDir::Dir(name) =>
+            {
+                if target != SearchTarget::Key && matcher.matches(name, path)
+                {
+                    result.dirs.push(path.clone());
+                }
+            },
+        }).await?;
         Ok(result)
     }
 
+    /// Return the capabilities (read, list, etc.) the token has on
+    /// the entry at `path`.
+    pub async fn capabilities(&self, path: &str) -> Result<Vec<String>, Error>
+    {
+        let req = self.buildReq(reqwest::Method::POST, &format!(
+            "{}v1/sys/capabilities-self", self.end_point))
+            .json(&json!({"paths": [self.dataPath(path)]}));
+        let res = self.send(req).await.map_err(
+            |e| error!(HTTPError, "Failed to send capabilities request: {}",
+                       e))?;
+        let status = res.status();
+        let res: serde_json::Value =
+            res.jsonLimited(self.config.max_response_bytes).await?;
+        self.printWarnings(&res);
+        if let Some(msg) = res["errors"][0].as_str()
+        {
+            return Err(requestError(status, format!(
+                "Failed to query capabilities: {}", msg)));
+        }
+        res["capabilities"].as_array().ok_or_else(
+            || rterr!("Capabilities is not a list"))?
+            .iter().map(|v| v.as_str().map(|c| c.to_owned()).ok_or_else(
+                || rterr!("Capability is not a string"))).collect()
+    }
+
     pub async fn listMounts(&self) -> Result<serde_json::Value, Error>
     {
-        self.client.get(&format!("{}v1/sys/mounts", self.end_point))
-            .send().await
+        self.send(self.client.get(format!("{}v1/sys/mounts", self.end_point)))
+            .await
             .map_err(|e| error!(HTTPError, "Failed to send request: {}", e))?
-            .json().await.map_err(|_| rterr!("Failed to parse JSON"))
+            .jsonLimited(self.config.max_response_bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::mock_vault::{MockVault, Request};
+
+    fn denied() -> (u16, String)
+    {
+        (403, json!({"errors": ["permission denied"]}).to_string())
+    }
+
+    /// A Vault where only the token `new` is valid, and AppRole login
+    /// gives `new`.
+    fn staleTokenHandler(req: &Request) -> (u16, String)
+    {
+        if req.path == "/v1/auth/approle/login"
+        {
+            return (200, json!({"auth": {"client_token": "new"}})
+                    .to_string());
+        }
+        if req.token.as_deref() != Some("new")
+        {
+            return denied();
+        }
+        match req.path.as_str()
+        {
+            "/v1/auth/token/lookup-self" =>
+                (200, json!({"data": {}}).to_string()),
+            "/v1/passwords/metadata/metrowind/" =>
+                (200, json!({"data": {"keys": ["a", "b/"]}}).to_string()),
+            _ => denied(),
+        }
+    }
+
+    fn sent(vault: &MockVault) -> Vec<(String, Option<String>)>
+    {
+        vault.requests().into_iter().map(|r| (r.path, r.token)).collect()
+    }
+
+    #[tokio::test]
+    async fn preferCacheLogsInAgainWhenRefused()
+    {
+        let vault = MockVault::start(staleTokenHandler).await;
+        let mut conf = vault.config();
+        conf.prefer_cache = true;
+        conf.approle_role_id = Some(String::from("role"));
+        setRuntimeInfo("token", Some("old"), &conf).unwrap();
+        let mut client = Client::new(&conf).unwrap();
+        client.login().await.unwrap();
+        assert_eq!(client.list("").await.unwrap().len(), 2);
+
+        let list = String::from("/v1/passwords/metadata/metrowind/");
+        let old = Some(String::from("old"));
+        assert_eq!(sent(&vault), vec![
+            (list.clone(), old.clone()),
+            (String::from("/v1/auth/token/lookup-self"), old),
+            (String::from("/v1/auth/approle/login"), None),
+            (list, Some(String::from("new")))]);
+        assert_eq!(getRuntimeInfo("token", &conf).unwrap().as_deref(),
+                   Some("new"));
+    }
+
+    #[tokio::test]
+    async fn preferCacheKeepsRealRefusals()
+    {
+        let vault = MockVault::start(|req| match req.path.as_str()
+        {
+            "/v1/auth/token/lookup-self" =>
+                (200, json!({"data": {}}).to_string()),
+            _ => denied(),
+        }).await;
+        let mut conf = vault.config();
+        conf.prefer_cache = true;
+        conf.approle_role_id = Some(String::from("role"));
+        setRuntimeInfo("token", Some("old"), &conf).unwrap();
+        let mut client = Client::new(&conf).unwrap();
+        client.login().await.unwrap();
+        assert!(matches!(client.list("").await, Err(Error::AuthError(_))));
+        // The token is valid, so there is no login, and a second
+        // refusal is not checked again.
+        assert!(client.list("").await.is_err());
+        assert_eq!(vault.requests().len(), 3);
+        assert!(vault.requests().iter().all(|r| !r.path.contains("login")));
+    }
+
+    #[tokio::test]
+    async fn refusalsAreAuthErrors()
+    {
+        let vault = MockVault::start(|_| denied()).await;
+        let conf = vault.config();
+        let client = Client::new(&conf).unwrap();
+        let errors = [
+            client.list("").await.err().unwrap(),
+            client.getRaw("a").await.unwrap_err(),
+            client.subkeys("a").await.unwrap_err(),
+            client.metadata("a").await.err().unwrap(),
+            client.mountConfig().await.err().unwrap(),
+            client.capabilities("a").await.unwrap_err(),
+            client.delete("a").await.unwrap_err()];
+        for e in errors
+        {
+            assert!(matches!(e, Error::AuthError(_)), "{}", e);
+        }
+    }
+
+    #[tokio::test]
+    async fn standbyRedirectsStayWithinConcurrency()
+    {
+        let active = MockVault::withDelay(
+            |_| (200, json!({"data": {"data": {"a": "b"}}}).to_string()),
+            std::time::Duration::from_millis(50)).await;
+        let leader = json!({"leader_address": active.end_point}).to_string();
+        // A standby that refuses instead of redirecting, so the leader
+        // has to be looked up.
+        let standby = MockVault::withDelay(
+            move |req| if req.path == "/v1/sys/leader"
+            {
+                (200, leader.clone())
+            }
+            else
+            {
+                (429, String::new())
+            }, std::time::Duration::from_millis(50)).await;
+        let mut conf = standby.config();
+        conf.concurrency = 2;
+        conf.follow_standby_redirect = true;
+        let client = Client::new(&conf).unwrap();
+        let gets = ["a", "b", "c", "d", "e", "f"].map(|p| client.getRaw(p));
+        for result in futures_util::future::join_all(gets).await
+        {
+            result.unwrap();
+        }
+        assert!(standby.maxInFlight() <= 2, "{}", standby.maxInFlight());
+        assert!(active.maxInFlight() <= 2, "{}", active.maxInFlight());
+        assert_eq!(active.requests().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn putChecksReadOnlyBeforeEncrypting()
+    {
+        let vault = MockVault::start(|_| (204, String::new())).await;
+        let mut conf = vault.config();
+        conf.read_only = true;
+        // Encrypting would fail, because there is no client key.
+        conf.client_encrypted_fields = vec![String::from("Password")];
+        let client = Client::new(&conf).unwrap();
+        let mut data = serde_json::Map::new();
+        data.insert(String::from("Password"), json!("x"));
+        let err = client.put("a", &data, None).await.unwrap_err();
+        assert!(err.message().contains("read-only"), "{}", err.message());
+        assert!(vault.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn logoutForgetsBatchTokenWithoutRevoking()
+    {
+        let vault = MockVault::start(|req| match req.path.as_str()
+        {
+            "/v1/auth/token/lookup-self" =>
+                (200, json!({"data": {"type": "batch"}}).to_string()),
+            _ => (400, json!({"errors": ["batch tokens cannot be revoked"]})
+                  .to_string()),
+        }).await;
+        let conf = vault.config();
+        setRuntimeInfo("token", Some("hvb.x"), &conf).unwrap();
+        let mut client = Client::new(&conf).unwrap();
+        client.loginUsingCachedToken().unwrap();
+        client.logout().await.unwrap();
+        assert!(vault.requests().iter().all(|r| !r.path.contains("revoke")));
+        assert_eq!(getRuntimeInfo("token", &conf).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn logoutClearsCacheWhenRevokeFails()
+    {
+        let vault = MockVault::start(|req| match req.path.as_str()
+        {
+            "/v1/auth/token/lookup-self" =>
+                (200, json!({"data": {"type": "service"}}).to_string()),
+            _ => (500, json!({"errors": ["internal error"]}).to_string()),
+        }).await;
+        let conf = vault.config();
+        setRuntimeInfo("token", Some("hvs.x"), &conf).unwrap();
+        let mut client = Client::new(&conf).unwrap();
+        client.loginUsingCachedToken().unwrap();
+        assert!(client.logout().await.is_err());
+        assert_eq!(getRuntimeInfo("token", &conf).unwrap(), None);
+    }
+
+    #[test]
+    fn globStarStaysInOneDirectory()
+    {
+        let path = |p: &str| p.split('/').fold(Path::new(), |p, c| p.pushed(c));
+        let matcher = Matcher::glob("team/*").unwrap();
+        assert!(matcher.matches("a", &path("team/a")));
+        assert!(!matcher.matches("b", &path("team/a/b")));
+        assert!(!matcher.matchesValue("team/a/b"));
+        let matcher = Matcher::glob("team/**/b").unwrap();
+        assert!(matcher.matches("b", &path("team/a/b")));
+    }
+
+    #[tokio::test]
+    async fn versionsRequireKVv2()
+    {
+        let vault = MockVault::start(|_| (204, String::new())).await;
+        let mut conf = vault.config();
+        conf.kv_version = 1;
+        let client = Client::new(&conf).unwrap();
+        let errors = [client.subkeys("a").await.unwrap_err(),
+                      client.metadata("a").await.err().unwrap(),
+                      client.deleteVersions("a", &[1]).await.unwrap_err(),
+                      client.undeleteVersions("a", &[1]).await.unwrap_err(),
+                      client.deleteLatest("a").await.unwrap_err()];
+        for e in errors
+        {
+            assert!(e.message().contains("requires KV v2"), "{}", e.message());
+        }
+        assert!(vault.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn oversizedResponseIsRejected()
+    {
+        let body = json!({"data": {"data": {"Password": "x".repeat(200)}}})
+            .to_string();
+        let limited = |limit| reqwest::Response::from(
+            http::Response::new(body.clone())).jsonLimited(limit);
+        assert!(limited(body.len()).await.is_ok());
+        let err = limited(body.len() - 1).await.unwrap_err();
+        assert!(err.message().contains("max_response_bytes"));
+
+        let vault = MockVault::start(move |_| (200, body.clone())).await;
+        let mut conf = vault.config();
+        conf.max_response_bytes = 100;
+        let client = Client::new(&conf).unwrap();
+        let err = client.get("a").await.unwrap_err();
+        assert!(err.message().contains("max_response_bytes"), "{}",
+                err.message());
+    }
+
+    #[tokio::test]
+    async fn preferCacheSkipsCheckingGivenToken()
+    {
+        let vault = MockVault::start(staleTokenHandler).await;
+        let mut conf = vault.config();
+        conf.prefer_cache = true;
+        conf.token = Some(String::from("new"));
+        let mut client = Client::new(&conf).unwrap();
+        client.login().await.unwrap();
+        assert!(vault.requests().is_empty());
+        assert_eq!(client.list("").await.unwrap().len(), 2);
+        assert_eq!(vault.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn readOnlyRefusesWrites()
+    {
+        let vault = MockVault::start(|_| (204, String::new())).await;
+        let mut conf = vault.config();
+        conf.read_only = true;
+        let client = Client::new(&conf).unwrap();
+        let errors = [
+            client.sendWrite(reqwest::Method::POST, "passwords/data/x",
+                             Some(json!({})), "write x").await.unwrap_err(),
+            client.put("a", &serde_json::Map::new(), None).await.unwrap_err(),
+            client.delete("a").await.unwrap_err(),
+            client.deleteLatest("a").await.unwrap_err(),
+            client.undeleteVersions("a", &[1]).await.unwrap_err()];
+        for e in errors
+        {
+            assert!(e.message().contains("read-only mode"), "{}",
+                    e.message());
+        }
+        assert!(vault.requests().is_empty());
+    }
+
+    /// Two self-signed certs, for testing bundles.
+    const PEM_BUNDLE: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBkTCCATegAwIBAgIUJNMOIbQ/59lu/RmRC4JD3R22RHgwCgYIKoZIzj0EAwIw
+HjEcMBoGA1UEAwwTdmF1bHQtaHVudGVyIHRlc3QgYTAeFw0yNjEwMTQxNzE2NDVa
+Fw0zNjEwMTExNzE2NDVaMB4xHDAaBgNVBAMME3ZhdWx0LWh1bnRlciB0ZXN0IGEw
+WTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAATxUJLpKX3qZFMvQbq0HSOqxkFbhrKY
+KZwYinyqc8SywAZAPfIl3PpAYOgzsRX9y8f46XgJLhAQzZ56zRlgboSZo1MwUTAd
+BgNVHQ4EFgQUITJUlJRjRwGNsDB9HkKZ89liWbIwHwYDVR0jBBgwFoAUITJUlJRj
+RwGNsDB9HkKZ89liWbIwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBF
+AiBLXlhVoceuSSMNiQdtf3rrYfLE7h9+DRN1CWtwPnLGywIhAKQvJv/tCNDw5zLR
+mG3EU8pSRxDWFtY5rdVzyZcPzneh
+-----END CERTIFICATE-----
+-----BEGIN CERTIFICATE-----
+MIIBkDCCATegAwIBAgIURlZbfvP1Wc33yr5DWfUsMPeh/JswCgYIKoZIzj0EAwIw
+HjEcMBoGA1UEAwwTdmF1bHQtaHVudGVyIHRlc3QgYjAeFw0yNjEwMTQxNzE2NDVa
+Fw0zNjEwMTExNzE2NDVaMB4xHDAaBgNVBAMME3ZhdWx0LWh1bnRlciB0ZXN0IGIw
+WTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAARxJvHSYy9UVRqshT7GkGWfQmhFQXxF
+lXz+dEqHqpccy1IvekYxFL5cS0sLyQ7jm5rVaR55Ow4WRtRS3VULWoA4o1MwUTAd
+BgNVHQ4EFgQU9VYieJJQ69Z85rkb1BFw64MvyaswHwYDVR0jBBgwFoAU9VYieJJQ
+69Z85rkb1BFw64MvyaswDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNHADBE
+AiAeo7QV0Lk9TfYJYYkXe1VMyhOapneX309fc2DPYm4uzgIgJI3tJ0sLWVMxjaB/
+0RMy1jJhkz+OgDfVQC7jFR6RsBA=
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn readsEveryCertInBundle()
+    {
+        let dir = std::env::temp_dir().join(format!(
+            "vault-hunter-test-certs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bundle = dir.join("bundle.pem");
+        std::fs::write(&bundle, PEM_BUNDLE).unwrap();
+        let single = PEM_BUNDLE.split_inclusive("-----END CERTIFICATE-----\n")
+            .next().unwrap();
+        std::fs::write(dir.join("single.crt"), single).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a cert").unwrap();
+
+        let bundle = bundle.to_string_lossy().into_owned();
+        assert_eq!(readCerts(&bundle).unwrap().len(), 2);
+        // A directory has the certs of all the .pem and .crt files.
+        assert_eq!(readCerts(&dir.to_string_lossy()).unwrap().len(), 3);
+        let mut conf = config::Config::default();
+        conf.ca_certs = vec![bundle];
+        conf.trust_system_roots = false;
+        let built = Client::new(&conf).is_ok();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(built);
+    }
+
+    #[tokio::test]
+    async fn walkVisitsTreeBreadthFirst()
+    {
+        let vault = MockVault::start(|req| match req.path
+            .trim_start_matches("/v1/passwords/metadata/metrowind")
+            .trim_end_matches('/')
+            {
+                "" => (200, json!({"data": {"keys": ["a", "d/", "e/"]}})
+                       .to_string()),
+                "/d" => (200, json!({"data": {"keys": ["f/", "b"]}})
+                         .to_string()),
+                "/d/f" => (200, json!({"data": {"keys": ["g"]}}).to_string()),
+                // An empty directory
+                _ => (404, json!({"errors": []}).to_string()),
+            }).await;
+        let conf = vault.config();
+        let client = Client::new(&conf).unwrap();
+        let mut visited = Vec::new();
+        client.walk(&Path::new(), |path, item| visited.push(match item
+        {
+            KeyOrDir::Key(_) => path.to_string(),
+            KeyOrDir::Dir(_) => format!("{}/", path),
+        })).await.unwrap();
+        assert_eq!(visited, ["a", "d/", "e/", "d/f/", "d/b", "d/f/g"]);
+        // The trailing slash of a directory is not part of its path.
+        let listed: Vec<_> = vault.requests().into_iter().map(|r| r.path)
+            .collect();
+        assert_eq!(listed, ["/v1/passwords/metadata/metrowind/",
+                            "/v1/passwords/metadata/metrowind/d",
+                            "/v1/passwords/metadata/metrowind/e",
+                            "/v1/passwords/metadata/metrowind/d/f"]);
+        assert!(client.list("e").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn warningsAreTakenFromResponse()
+    {
+        let res = json!({"data": {}, "warnings": [
+            "Endpoint is deprecated", null, "TTL is capped"]});
+        assert_eq!(responseWarnings(&res),
+                   ["Endpoint is deprecated", "TTL is capped"]);
+        assert!(responseWarnings(&json!({"warnings": null})).is_empty());
+        assert!(responseWarnings(&json!({})).is_empty());
+    }
+
+    #[tokio::test]
+    async fn healthAcceptsAnyCode()
+    {
+        for (code, status) in [(200, "active"), (429, "standby"),
+                               (503, "sealed"), (530, "unknown(530)"),
+                               (418, "unknown(418)")]
+        {
+            assert_eq!(HealthStatus::fromHTTPStatus(code).to_string(), status);
+        }
+        let vault = MockVault::start(|_| (530, String::new())).await;
+        let conf = vault.config();
+        let client = Client::new(&conf).unwrap();
+        assert_eq!(client.health().await.unwrap().to_string(), "unknown(530)");
     }
 }
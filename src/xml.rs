@@ -0,0 +1,121 @@
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::vault_client::StringMap;
+
+/// An entry in the XML export.
+#[derive(Clone)]
+pub struct Entry
+{
+    pub path: String,
+    /// KV v2 version of the entry. Only available if the export was
+    /// done with metadata or deduplication.
+    pub version: Option<String>,
+    /// Creation time of the version. Only available if the export
+    /// was done with metadata.
+    pub created_time: Option<String>,
+    pub data: StringMap,
+}
+
+#[derive(Deserialize)]
+struct XMLKeyValue
+{
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct XMLEntry
+{
+    #[serde(rename = "@path")]
+    path: String,
+    #[serde(rename = "@version", default)]
+    version: Option<String>,
+    #[serde(rename = "@created_time", default)]
+    created_time: Option<String>,
+    #[serde(rename = "kv", default)]
+    kvs: Vec<XMLKeyValue>,
+}
+
+#[derive(Deserialize)]
+struct XMLExport
+{
+    #[serde(rename = "entry", default)]
+    entries: Vec<XMLEntry>,
+}
+
+fn writeEntry(writer: &mut quick_xml::Writer<std::io::Cursor<Vec<u8>>>,
+              entry: &Entry) -> Result<(), Error>
+{
+    let mut element = writer.create_element("entry")
+        .with_attribute(("path", entry.path.as_str()));
+    if let Some(v) = &entry.version
+    {
+        element = element.with_attribute(("version", v.as_str()));
+    }
+    if let Some(t) = &entry.created_time
+    {
+        element = element.with_attribute(("created_time", t.as_str()));
+    }
+    element.write_inner_content(|writer| {
+            for (key, value) in &entry.data
+            {
+                writer.create_element("kv").write_inner_content(|kv_writer| {
+                    kv_writer.create_element("key").write_text_content(
+                        quick_xml::events::BytesText::new(key))?;
+                    kv_writer.create_element("value").write_text_content(
+                        quick_xml::events::BytesText::new(value))?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        }).map_err(|e| rterr!("Failed to write entry: {}", e))?;
+    Ok(())
+}
+
+/// Serialize entries to XML.
+pub fn write(entries: &[Entry]) -> Result<Vec<u8>, Error>
+{
+    let mut writer = quick_xml::Writer::new_with_indent(
+        std::io::Cursor::new(Vec::new()), 32, 2);
+    for entry in entries
+    {
+        writeEntry(&mut writer, entry)?;
+    }
+    Ok(writer.into_inner().into_inner())
+}
+
+/// Parse an XML export. The `version` and `created_time` attributes
+/// are optional, so exports with and without metadata can be read.
+pub fn parse(xml: &[u8]) -> Result<Vec<Entry>, Error>
+{
+    let content = std::str::from_utf8(xml).map_err(
+        |_| rterr!("XML export is not valid UTF-8"))?;
+    // The entries are written at the top level without a root
+    // element.
+    let wrapped = format!("<export>{}</export>", content);
+    let export: XMLExport = quick_xml::de::from_str(&wrapped).map_err(
+        |e| rterr!("Failed to parse XML export: {}", e))?;
+    Ok(export.entries.into_iter().map(|e| Entry {
+        path: e.path,
+        version: e.version,
+        created_time: e.created_time,
+        data: e.kvs.into_iter().map(|kv| (kv.key, kv.value)).collect(),
+    }).collect())
+}
+
+/// Merge `new` entries into `old` ones. An entry in `new` replaces
+/// the entry with the same path in `old`; entries only in `old` are
+/// kept.
+pub fn merge(old: Vec<Entry>, new: Vec<Entry>) -> Vec<Entry>
+{
+    let mut by_path: IndexMap<String, Entry> = old.into_iter()
+        .map(|e| (e.path.clone(), e)).collect();
+    for entry in new
+    {
+        by_path.insert(entry.path.clone(), entry);
+    }
+    by_path.into_values().collect()
+}